@@ -0,0 +1,417 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use songbird::input::{File, Input};
+use tokio::process::Command as TokioCommand;
+use tokio::sync::Semaphore;
+use tracing::{debug, info, warn};
+
+use crate::commands::music::metadata::{TrackMetadata, TrackSource};
+use crate::config::Config;
+use crate::ytdlp::{CookieCopy, YtDlp};
+
+// Audio of tracks queued more than once, downloaded with yt-dlp so replays
+// come from disk. Each entry is the audio file as yt-dlp fetched it plus a
+// `<key>.json` record of its metadata; the audio file's modification time
+// marks its last use for LRU eviction.
+
+// A track is downloaded once it has been queued this often
+const PLAYS_BEFORE_CACHING: u32 = 2;
+
+// Tracks whose plays are counted at once; the ones counted longest ago are
+// forgotten first
+const MAX_COUNTED_TRACKS: usize = 10_000;
+
+// Long mixes and streams would crowd out everything else
+const MAX_CACHED_DURATION: Duration = Duration::from_secs(60 * 60);
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// The format songbird's own yt-dlp source streams
+const AUDIO_FORMAT: &str = "ba[abr>0][vcodec=none]/best";
+
+lazy_static! {
+    static ref YOUTUBE_ID: Regex =
+        Regex::new(r"(?:youtube\.com/(?:watch\?(?:.*&)?v=|shorts/)|youtu\.be/)([A-Za-z0-9_-]{11})")
+            .unwrap();
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheRecord {
+    file: String,
+    metadata: TrackMetadata,
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    last_used: SystemTime,
+    metadata: TrackMetadata,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    total_size: u64,
+    plays: HashMap<String, u32>,
+    // Keys of `plays` in the order they were first counted
+    counted: VecDeque<String>,
+    downloading: HashSet<String>,
+    hits: u64,
+    misses: u64,
+}
+
+pub struct CacheStats {
+    pub entries: usize,
+    pub size: u64,
+    pub max_size: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Clone)]
+pub struct TrackCache {
+    dir: PathBuf,
+    // Bytes, 0 disables the cache
+    max_size: u64,
+    ytdlp_path: String,
+    ytdlp_args: Vec<String>,
+    ytdlp_cookies: Option<PathBuf>,
+    state: Arc<Mutex<CacheState>>,
+    downloads: Arc<Semaphore>,
+}
+
+// Same video, same entry, however the link was written. Searches have no
+// stable source, everything else is keyed by its URL.
+fn cache_key(url: &str) -> Option<String> {
+    if let Some(captures) = YOUTUBE_ID.captures(url) {
+        return Some(format!("yt-{}", &captures[1]));
+    }
+
+    if !url.starts_with("http") {
+        return None;
+    }
+
+    // FNV-1a, stable across builds unlike `DefaultHasher`
+    let hash = url.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    Some(format!("url-{:016x}", hash))
+}
+
+impl TrackCache {
+    pub fn open(config: &Config) -> io::Result<Self> {
+        let cache = Self {
+            dir: config.cache_path.clone(),
+            max_size: config.cache_size * 1024 * 1024,
+            ytdlp_path: config.ytdlp_path.clone(),
+            ytdlp_args: config.ytdlp_args(),
+            ytdlp_cookies: config.ytdlp_cookies.clone(),
+            state: Arc::new(Mutex::new(CacheState::default())),
+            downloads: Arc::new(Semaphore::new(1)),
+        };
+
+        if cache.is_enabled() {
+            fs::create_dir_all(&cache.dir)?;
+            cache.load()?;
+        }
+
+        Ok(cache)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_size > 0
+    }
+
+    // Reads the records left by earlier runs, dropping half-finished downloads
+    fn load(&self) -> io::Result<()> {
+        let mut state = self.state.lock().expect("Cache state poisoned");
+
+        for entry in fs::read_dir(&self.dir)?.flatten() {
+            let path = entry.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            if name.contains(".partial.") {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+
+            let Some(key) = name.strip_suffix(".json") else {
+                continue;
+            };
+
+            let record = fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<CacheRecord>(&json).ok());
+            let audio = record.as_ref().map(|record| self.dir.join(&record.file));
+            let file_info = audio.as_ref().and_then(|audio| fs::metadata(audio).ok());
+
+            match (record, audio, file_info) {
+                (Some(record), Some(audio), Some(file_info)) => {
+                    state.total_size += file_info.len();
+                    state.entries.insert(
+                        key.to_string(),
+                        CacheEntry {
+                            path: audio,
+                            size: file_info.len(),
+                            last_used: file_info.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                            metadata: record.metadata,
+                        },
+                    );
+                }
+                _ => {
+                    debug!("TrackCache: Dropping incomplete entry {}", key);
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+
+        info!(
+            "TrackCache: {} tracks, {} MB in {}",
+            state.entries.len(),
+            state.total_size / 1024 / 1024,
+            self.dir.display()
+        );
+
+        Ok(())
+    }
+
+    // The cached metadata of a URL the user gave us, so yt-dlp needn't even
+    // be asked for it
+    pub fn metadata(&self, url: &str) -> Option<TrackMetadata> {
+        let key = cache_key(url)?;
+        let state = self.state.lock().expect("Cache state poisoned");
+
+        state.entries.get(&key).map(|entry| entry.metadata.clone())
+    }
+
+    // Plays the track from disk if it's cached. Otherwise the usual source is
+    // used and the track is downloaded once it has been queued often enough.
+    pub fn input(&self, metadata: &TrackMetadata, ytdlp: &YtDlp) -> Input {
+        match self.get_or_record(metadata) {
+            Some(path) => File::new(path).into(),
+            None => metadata.input(ytdlp),
+        }
+    }
+
+    // Like `input`, for tracks whose yt-dlp source has already been created
+    pub fn input_or(&self, metadata: &TrackMetadata, source: impl Into<Input>) -> Input {
+        match self.get_or_record(metadata) {
+            Some(path) => File::new(path).into(),
+            None => source.into(),
+        }
+    }
+
+    fn get_or_record(&self, metadata: &TrackMetadata) -> Option<PathBuf> {
+        if !self.is_enabled() || metadata.source != TrackSource::YoutubeDl {
+            return None;
+        }
+        let key = cache_key(&metadata.url)?;
+
+        let mut state = self.state.lock().expect("Cache state poisoned");
+        if let Some(entry) = state.entries.get_mut(&key) {
+            entry.last_used = SystemTime::now();
+            let path = entry.path.clone();
+            state.hits += 1;
+            drop(state);
+
+            // Kept on disk so the order survives restarts. This runs while
+            // the call is locked, so the file system is left to a thread.
+            let touched = path.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = fs::File::options()
+                    .write(true)
+                    .open(&touched)
+                    .and_then(|file| file.set_modified(SystemTime::now()))
+                {
+                    debug!("TrackCache: Could not touch {}: {}", touched.display(), e);
+                }
+            });
+            return Some(path);
+        }
+
+        state.misses += 1;
+        let plays = match state.plays.get_mut(&key) {
+            Some(plays) => {
+                *plays += 1;
+                *plays
+            }
+            None => {
+                state.plays.insert(key.clone(), 1);
+                state.counted.push_back(key.clone());
+                while state.counted.len() > MAX_COUNTED_TRACKS {
+                    if let Some(oldest) = state.counted.pop_front() {
+                        state.plays.remove(&oldest);
+                    }
+                }
+                1
+            }
+        };
+
+        let cacheable = metadata
+            .duration
+            .is_some_and(|duration| duration <= MAX_CACHED_DURATION);
+        if plays >= PLAYS_BEFORE_CACHING && cacheable && state.downloading.insert(key.clone()) {
+            // Counted again from scratch if the download fails
+            state.plays.remove(&key);
+            state.counted.retain(|counted| *counted != key);
+            let cache = self.clone();
+            let metadata = metadata.clone();
+            tokio::spawn(async move {
+                cache.download(key, metadata).await;
+            });
+        }
+
+        None
+    }
+
+    async fn download(&self, key: String, metadata: TrackMetadata) {
+        let _permit = self.downloads.acquire().await.expect("Cache semaphore closed");
+
+        let result = self.fetch(&key, &metadata).await;
+        let mut state = self.state.lock().expect("Cache state poisoned");
+        state.downloading.remove(&key);
+
+        let (path, size) = match result {
+            Ok(downloaded) => downloaded,
+            Err(e) => {
+                warn!("TrackCache: Could not download {}: {}", metadata.url, e);
+                return;
+            }
+        };
+
+        if size > self.max_size {
+            debug!("TrackCache: {} is larger than the whole cache", metadata.url);
+            let _ = fs::remove_file(&path);
+            let _ = fs::remove_file(self.record_path(&key));
+            return;
+        }
+
+        state.total_size += size;
+        state.entries.insert(
+            key.clone(),
+            CacheEntry {
+                path,
+                size,
+                last_used: SystemTime::now(),
+                metadata,
+            },
+        );
+        info!("TrackCache: Stored {} ({} KB)", key, size / 1024);
+
+        self.evict(&mut state);
+    }
+
+    // Downloads to a partial file first so a crash never leaves a truncated
+    // entry behind
+    async fn fetch(&self, key: &str, metadata: &TrackMetadata) -> io::Result<(PathBuf, u64)> {
+        let template = self.dir.join(format!("{}.partial.%(ext)s", key));
+        let cookies = CookieCopy::for_run(self.ytdlp_cookies.as_deref()).await?;
+        let command = TokioCommand::new(&self.ytdlp_path)
+            .args(&self.ytdlp_args)
+            .args(cookies.iter().flat_map(CookieCopy::args))
+            .args(["-f", AUDIO_FORMAT, "--no-playlist", "--quiet", "--no-warnings"])
+            .args(["--print", "after_move:filepath", "-o"])
+            .arg(&template)
+            .arg(&metadata.url)
+            .kill_on_drop(true)
+            .output();
+
+        let output = tokio::time::timeout(DOWNLOAD_TIMEOUT, command)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "yt-dlp took too long"))??;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "yt-dlp exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let partial = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+        let extension = partial
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("audio");
+        let file = format!("{}.{}", key, extension);
+        let path = self.dir.join(&file);
+        fs::rename(&partial, &path)?;
+
+        let record = CacheRecord {
+            file,
+            metadata: metadata.clone(),
+        };
+        fs::write(self.record_path(key), serde_json::to_vec(&record)?)?;
+
+        Ok((path.clone(), fs::metadata(&path)?.len()))
+    }
+
+    fn record_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    // Drops the least recently used tracks until the cache fits its cap.
+    // Tracks still playing keep reading their open file.
+    fn evict(&self, state: &mut CacheState) {
+        while state.total_size > self.max_size {
+            let Some(key) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+
+            if let Some(entry) = state.entries.remove(&key) {
+                debug!("TrackCache: Evicting {}", key);
+                state.total_size -= entry.size;
+                remove_entry_files(&entry.path, &self.record_path(&key));
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().expect("Cache state poisoned");
+
+        CacheStats {
+            entries: state.entries.len(),
+            size: state.total_size,
+            max_size: self.max_size,
+            hits: state.hits,
+            misses: state.misses,
+        }
+    }
+
+    // Returns the number of tracks removed
+    pub fn clear(&self) -> usize {
+        let mut state = self.state.lock().expect("Cache state poisoned");
+        let removed = state.entries.len();
+
+        for (key, entry) in state.entries.drain() {
+            remove_entry_files(&entry.path, &self.record_path(&key));
+        }
+        state.total_size = 0;
+        state.plays.clear();
+        state.counted.clear();
+
+        removed
+    }
+}
+
+fn remove_entry_files(audio: &Path, record: &Path) {
+    for path in [record, audio] {
+        if let Err(e) = fs::remove_file(path) {
+            warn!("TrackCache: Could not remove {}: {}", path.display(), e);
+        }
+    }
+}
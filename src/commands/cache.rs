@@ -0,0 +1,72 @@
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::prelude::*;
+use serenity::model::Timestamp;
+use serenity::prelude::*;
+use tracing::info;
+
+use crate::commands::utils::{get_track_cache, send_success_message, send_warning};
+
+#[command]
+#[owners_only]
+#[sub_commands(cache_stats, cache_clear)]
+async fn cache(ctx: &Context, msg: &Message) -> CommandResult {
+    show_stats(ctx, msg).await
+}
+
+#[command("stats")]
+#[owners_only]
+async fn cache_stats(ctx: &Context, msg: &Message) -> CommandResult {
+    show_stats(ctx, msg).await
+}
+
+async fn show_stats(ctx: &Context, msg: &Message) -> CommandResult {
+    let cache = get_track_cache(ctx).await;
+    if !cache.is_enabled() {
+        send_warning(ctx, msg, "The track cache is disabled, set `cache_size` to enable it.").await?;
+        return Ok(());
+    }
+
+    let stats = cache.stats();
+    let lookups = stats.hits + stats.misses;
+    let hit_rate = (stats.hits * 100)
+        .checked_div(lookups)
+        .map_or_else(|| "-".to_string(), |rate| format!("{}%", rate));
+
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(":floppy_disk: Track cache")
+        .field("Tracks", stats.entries.to_string(), true)
+        .field(
+            "Size",
+            format!("{} / {} MB", stats.size / 1024 / 1024, stats.max_size / 1024 / 1024),
+            true,
+        )
+        .field("Hit rate", format!("{} of {} ({})", stats.hits, lookups, hit_rate), true)
+        .timestamp(Timestamp::now());
+    msg.channel_id
+        .send_message(&ctx.http, CreateMessage::default().add_embed(embed))
+        .await?;
+
+    Ok(())
+}
+
+#[command("clear")]
+#[owners_only]
+async fn cache_clear(ctx: &Context, msg: &Message) -> CommandResult {
+    let cache = get_track_cache(ctx).await;
+    if !cache.is_enabled() {
+        send_warning(ctx, msg, "The track cache is disabled, set `cache_size` to enable it.").await?;
+        return Ok(());
+    }
+
+    let removed = cache.clear();
+    info!("cache_clear: {} removed {} cached tracks", msg.author.name, removed);
+    send_success_message(
+        ctx,
+        msg,
+        &format!(":wastebasket: Removed {} tracks from the cache", removed),
+    )
+    .await
+}
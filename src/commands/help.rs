@@ -5,13 +5,13 @@ use serenity::model::prelude::*;
 use serenity::model::Timestamp;
 use serenity::prelude::*;
 
-use std::env;
+use crate::commands::utils::get_prefix;
 
 // Custom help menu
 
 #[command]
 pub async fn help(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let prefix = env::var("PREFIX").expect("Set your PREFIX environment variable!");
+    let prefix = get_prefix(ctx, msg).await;
 
     let menu_choice_str: String = match args.single::<String>() {
         Ok(menu_choice) => menu_choice,
@@ -29,19 +29,35 @@ pub async fn help(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult
                     .description(format!("Hi i'm RMusicBot. My prefix is `{}`", prefix))
                     .fields(match menu_choice {
                         "general" => {
-                            vec![("help", "Displays this help menu", true)]
+                            vec![
+                                ("help", "Displays this help menu", true),
+                                ("settings", "Shows or changes this server's settings", true),
+                            ]
                         }
 
                         "music" => {
                             vec![
                                 ("leave", "Leaves a music channel", true),
-                                ("play", "Play / queue a song from a YouTube URL", true),
+                                ("play", "Play / queue a song from a YouTube, Spotify, Apple Music or Deezer URL or an attached audio file", true),
+                                ("search", "Lists search results, `ytm:` / `sc:` pick YouTube Music / SoundCloud", true),
                                 ("stop", "Stops current playlist", true),
                                 ("skip", "Skips the current song", true),
                                 ("pause", "Pauses the current song", true),
                                 ("resume", "Resumes the current song", true),
                                 ("nowplaying", "Shows info about current song", true),
                                 ("clear", "Clear the queue", true),
+                                ("history", "Shows recently played songs", true),
+                                ("previous", "Plays the previous song again", true),
+                                ("queue", "Shows the upcoming songs", true),
+                                ("queue export", "Uploads the queue as M3U8 and JSON", true),
+                                ("queue import", "Queues the songs of an attached M3U8 / JSON file", true),
+                                ("autoplay", "Toggles playing related songs when the queue ends", true),
+                                ("filter", "Toggles bassboost, nightcore, vaporwave, 8d or karaoke, `filter eq` sets bands", true),
+                                ("speed", "Plays faster or slower (0.5 to 2), keeping the pitch", true),
+                                ("pitch", "Moves the pitch by semitones, keeping the speed", true),
+                                ("playlist", "Saves, loads and edits named playlists", true),
+                                ("radio", "Tunes in to one of this server's saved radio stations", true),
+                                ("library", "Searches the local music library, play it with `play local:`", true),
                             ]
                         }
 
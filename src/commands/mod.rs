@@ -1,3 +1,5 @@
+pub mod cache;
 pub mod help;
 pub mod music;
+pub mod settings;
 pub mod utils;
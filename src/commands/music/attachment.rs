@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use reqwest::Client as HttpClient;
+use serenity::model::prelude::*;
+use songbird::input::codecs::{get_codec_registry, get_probe};
+use songbird::input::{HttpRequest, Input};
+use symphonia::core::codecs::CodecParameters;
+use symphonia::core::meta::{MetadataRevision, StandardTagKey};
+use tracing::{debug, warn};
+
+use crate::commands::music::metadata::{StreamTitle, TrackMetadata, TrackSource};
+use crate::commands::music::resolver::{ResolveError, ResolvedTrack};
+use crate::filters::PlaybackProgress;
+
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "ogg", "flac", "wav", "m4a", "opus"];
+
+pub fn is_audio_attachment(attachment: &Attachment) -> bool {
+    attachment
+        .filename
+        .rsplit_once('.')
+        .is_some_and(|(_, extension)| {
+            AUDIO_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+#[derive(Default)]
+pub struct FileTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl FileTags {
+    pub fn read_revision(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => self.title = Some(tag.value.to_string()),
+                Some(StandardTagKey::Artist) => self.artist = Some(tag.value.to_string()),
+                Some(StandardTagKey::Album) => self.album = Some(tag.value.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    // Length of the track from its stream header, if the format declares one
+    pub fn read_duration(&mut self, params: &CodecParameters) {
+        if let (Some(frames), Some(time_base)) = (params.n_frames, params.time_base) {
+            let time = time_base.calc_time(frames);
+            self.duration =
+                Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac));
+        }
+    }
+}
+
+// Reads title and artist from the file's tags and its length from the stream
+// header. Tags inside the container (e.g. Vorbis comments) win over ones found
+// while probing (e.g. ID3).
+fn read_tags(input: &mut Input) -> FileTags {
+    let mut tags = FileTags::default();
+
+    if let Ok(metadata) = input.metadata() {
+        if let Some(probed) = metadata.probe.get() {
+            if let Some(revision) = probed.current() {
+                tags.read_revision(revision);
+            }
+        }
+        if let Some(revision) = metadata.format.current() {
+            tags.read_revision(revision);
+        }
+    }
+
+    if let Input::Live(live, _) = input {
+        if let Some(parsed) = live.parsed() {
+            if let Some(track) = parsed
+                .format
+                .tracks()
+                .iter()
+                .find(|track| track.id == parsed.track_id)
+            {
+                tags.read_duration(&track.codec_params);
+            }
+        }
+    }
+
+    tags
+}
+
+// Opens an audio file served over plain HTTP, `name` being shown when the
+// file carries no title tag. Parsing the headers up front gives us the tags
+// and catches files symphonia can't decode before they reach the queue.
+pub async fn read_audio_file(
+    http_client: &HttpClient,
+    url: &str,
+    name: &str,
+    requester: &User,
+) -> Result<ResolvedTrack, ResolveError> {
+    let input: Input = HttpRequest::new(http_client.clone(), url.to_string()).into();
+    let mut input = match input.make_playable_async(get_codec_registry(), get_probe()).await {
+        Ok(input) => input,
+        Err(e) => {
+            warn!("read_audio_file: Failed to read {}: {}", url, e);
+            return Err(ResolveError::Failed("Could not read that audio file.".to_string()));
+        }
+    };
+
+    let tags = read_tags(&mut input);
+    let title = match (tags.artist, tags.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title,
+        (_, None) => name.to_string(),
+    };
+    debug!("read_audio_file: Read '{}' from {}", title, url);
+
+    Ok(ResolvedTrack {
+        metadata: TrackMetadata {
+            title,
+            url: url.to_string(),
+            duration: tags.duration,
+            requester_id: requester.id,
+            requester_name: requester.name.clone(),
+            autoplay: false,
+            source: TrackSource::Http,
+            stream_title: StreamTitle::default(),
+            progress: PlaybackProgress::default(),
+        },
+        input,
+    })
+}
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tracing::{debug, info, warn};
+
+use crate::commands::music::history::TrackHistory;
+use crate::commands::music::metadata::TrackMetadata;
+use crate::commands::music::play::resolve_track;
+use crate::commands::utils::{
+    get_guild_id_from_message, send_error_message, send_success_message, send_warning,
+};
+use crate::ytdlp::{YtDlp, YtDlpSource};
+use crate::GuildSettingsKey;
+
+// How many recently played tracks are excluded from autoplay picks
+const AUTOPLAY_HISTORY_WINDOW: usize = 25;
+
+// How many entries of a mix / search result are considered
+const AUTOPLAY_CANDIDATES: usize = 25;
+
+lazy_static! {
+    static ref VIDEO_ID: Regex = Regex::new(r"(?:[?&]v=|youtu\.be/|/shorts/)([A-Za-z0-9_-]{11})").unwrap();
+}
+
+#[command]
+#[only_in(guilds)]
+async fn autoplay(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let settings = {
+        let data = ctx.data.read().await;
+        data.get::<GuildSettingsKey>()
+            .cloned()
+            .expect("Should exist in typemap")
+    };
+
+    let enabled = match args.single::<String>().ok().as_deref() {
+        Some("on") => true,
+        Some("off") => false,
+        None => !settings.get(guild_id).await.autoplay,
+        Some(_) => {
+            send_warning(ctx, msg, "Use the command like this: autoplay [on|off]").await?;
+            return Ok(());
+        }
+    };
+
+    if let Err(e) = settings.update(guild_id, |s| s.autoplay = enabled).await {
+        warn!("autoplay: Failed to save setting for guild {:?}: {}", guild_id, e);
+        send_error_message(ctx, msg, "Could not save the setting.").await?;
+        return Ok(());
+    }
+    info!("autoplay: Guild {:?} set autoplay to {}", guild_id, enabled);
+
+    let title = if enabled {
+        ":infinity: Autoplay enabled! Related songs will play when the queue runs out."
+    } else {
+        ":infinity: Autoplay disabled."
+    };
+    send_success_message(ctx, msg, title).await?;
+
+    Ok(())
+}
+
+struct Candidate {
+    id: String,
+    url: String,
+}
+
+// Picks a follow-up for `last`: first from the YouTube mix of the track, then
+// from a search for its title. Anything played recently is skipped.
+pub async fn find_related_track(
+    ytdlp: &YtDlp,
+    history: &TrackHistory,
+    guild_id: GuildId,
+    last: &TrackMetadata,
+) -> Option<(YtDlpSource, TrackMetadata)> {
+    let mut played: HashSet<String> = history
+        .recent(guild_id, AUTOPLAY_HISTORY_WINDOW)
+        .await
+        .iter()
+        .filter_map(|entry| video_id(&entry.metadata.url))
+        .collect();
+    if let Some(id) = video_id(&last.url) {
+        played.insert(id);
+    }
+
+    let mut lookups = Vec::new();
+    if let Some(id) = video_id(&last.url) {
+        lookups.push(format!("https://www.youtube.com/watch?v={id}&list=RD{id}"));
+    }
+    lookups.push(format!("ytsearch{}:{}", AUTOPLAY_CANDIDATES, last.title));
+
+    for lookup in lookups {
+        let candidate = fetch_candidates(ytdlp, &lookup)
+            .await
+            .into_iter()
+            .find(|candidate| !played.contains(&candidate.id));
+
+        let Some(candidate) = candidate else {
+            debug!("find_related_track: No unplayed candidates from {}", lookup);
+            continue;
+        };
+
+        match resolve_track(
+            ytdlp,
+            &candidate.url,
+            last.requester_id,
+            &last.requester_name,
+        )
+        .await
+        {
+            Ok((mut metadata, source)) => {
+                metadata.autoplay = true;
+                return Some((source, metadata));
+            }
+            Err(e) => warn!("find_related_track: Failed to resolve {}: {:?}", candidate.url, e),
+        }
+    }
+
+    None
+}
+
+async fn fetch_candidates(ytdlp: &YtDlp, lookup: &str) -> Vec<Candidate> {
+    let playlist_end = AUTOPLAY_CANDIDATES.to_string();
+    let output = ytdlp
+        .output(&["-j", "--flat-playlist", "--playlist-end", &playlist_end, lookup])
+        .await;
+
+    let stdout = match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(e) => {
+            warn!("fetch_candidates: yt-dlp command failed: {:?}", e);
+            return Vec::new();
+        }
+    };
+
+    stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|entry| {
+            let id = entry.get("id")?.as_str()?.to_string();
+            Some(Candidate {
+                url: format!("https://www.youtube.com/watch?v={id}"),
+                id,
+            })
+        })
+        .collect()
+}
+
+fn video_id(url: &str) -> Option<String> {
+    VIDEO_ID.captures(url).map(|cap| cap[1].to_string())
+}
@@ -7,6 +7,8 @@ use serenity::{
     model::{channel::Message, Timestamp}, builder::{CreateMessage, CreateEmbed},
 };
 
+use crate::commands::utils::get_crossfades;
+
 
 #[command]
 #[only_in(guilds)]
@@ -22,6 +24,7 @@ async fn clear(ctx: &Context, msg: &Message) -> CommandResult {
         Some(handler_lock) => {
             let handler = handler_lock.lock().await;
             handler.queue().stop();
+            get_crossfades(ctx).await.stop(guild_id);
             send_clear_message(ctx, msg, 0xffffff, "Queue emptied!").await?;
         }
         None => {
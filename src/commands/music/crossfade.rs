@@ -0,0 +1,480 @@
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serenity::async_trait;
+use serenity::model::id::GuildId;
+use songbird::events::{Event, EventContext, EventHandler as VoiceEventHandler};
+use songbird::tracks::{LoopState, PlayMode, TrackHandle};
+use songbird::Songbird;
+use tracing::{debug, info};
+
+use crate::commands::music::metadata::TrackMetadata;
+use crate::settings::GuildSettingsStore;
+
+// Crossfading takes the playing track out of the queue once it nears its end,
+// so the queue starts the next one, and fades the two into each other by
+// their volumes. Songbird mixes every track of a call, so both are heard.
+
+// Seconds
+pub const MAX_CROSSFADE: u64 = 12;
+
+// How often the playing track is checked for being close to its end
+pub const CROSSFADE_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+// How often volumes are moved during a fade
+const FADE_STEP: Duration = Duration::from_millis(50);
+
+// A track fading out, and the one fading in once the queue has moved on to it
+struct Fade {
+    outgoing: TrackHandle,
+    incoming: Option<Incoming>,
+}
+
+struct Incoming {
+    handle: TrackHandle,
+    // What it plays at once faded in
+    volume: f32,
+    // Cleared once someone else set its volume
+    faded: bool,
+}
+
+// Tracks fading out, per guild. They have left the queue, so `pause`, `skip`
+// and the like reach them through here rather than through the queue.
+#[derive(Clone, Default)]
+pub struct Crossfades {
+    guilds: Arc<Mutex<HashMap<GuildId, Vec<Fade>>>>,
+}
+
+impl Crossfades {
+    // `false` if the track is fading already
+    fn start(&self, guild_id: GuildId, handle: &TrackHandle) -> bool {
+        let mut guilds = self.guilds.lock().expect("Crossfade lock poisoned");
+        let fades = guilds.entry(guild_id).or_default();
+        if fades.iter().any(|fade| fade.outgoing.uuid() == handle.uuid()) {
+            return false;
+        }
+
+        fades.push(Fade {
+            outgoing: handle.clone(),
+            incoming: None,
+        });
+        true
+    }
+
+    fn is_fading(&self, guild_id: GuildId, handle: &TrackHandle) -> bool {
+        let guilds = self.guilds.lock().expect("Crossfade lock poisoned");
+        guilds
+            .get(&guild_id)
+            .is_some_and(|fades| fades.iter().any(|fade| fade.outgoing.uuid() == handle.uuid()))
+    }
+
+    fn handed_over(&self, guild_id: GuildId, outgoing: &TrackHandle, incoming: &TrackHandle, volume: f32) {
+        let mut guilds = self.guilds.lock().expect("Crossfade lock poisoned");
+        let fade = guilds
+            .get_mut(&guild_id)
+            .and_then(|fades| fades.iter_mut().find(|fade| fade.outgoing.uuid() == outgoing.uuid()));
+        if let Some(fade) = fade {
+            fade.incoming = Some(Incoming {
+                handle: incoming.clone(),
+                volume,
+                faded: true,
+            });
+        }
+    }
+
+    // Sets the volumes of a fade, unless `skip` or `stop` ended it. Done
+    // under the lock, so a fade that just ended can't undo what they set.
+    fn apply(&self, guild_id: GuildId, outgoing: &TrackHandle, volumes: Volumes) -> bool {
+        let mut guilds = self.guilds.lock().expect("Crossfade lock poisoned");
+        let fade = guilds
+            .get_mut(&guild_id)
+            .and_then(|fades| fades.iter_mut().find(|fade| fade.outgoing.uuid() == outgoing.uuid()));
+        let Some(fade) = fade else {
+            return false;
+        };
+
+        if let Some(volume) = volumes.outgoing {
+            drop(fade.outgoing.set_volume(volume));
+        }
+        if let Some(incoming) = &mut fade.incoming {
+            match volumes.incoming {
+                Some(volume) => drop(incoming.handle.set_volume(volume)),
+                None => incoming.faded = false,
+            }
+        }
+        true
+    }
+
+    fn finish(&self, guild_id: GuildId, handle: &TrackHandle) {
+        let mut guilds = self.guilds.lock().expect("Crossfade lock poisoned");
+        if let Some(fades) = guilds.get_mut(&guild_id) {
+            fades.retain(|fade| fade.outgoing.uuid() != handle.uuid());
+            if fades.is_empty() {
+                guilds.remove(&guild_id);
+            }
+        }
+    }
+
+    fn fading(&self, guild_id: GuildId) -> Vec<TrackHandle> {
+        let guilds = self.guilds.lock().expect("Crossfade lock poisoned");
+        guilds
+            .get(&guild_id)
+            .map(|fades| fades.iter().map(|fade| fade.outgoing.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn pause(&self, guild_id: GuildId) {
+        for handle in self.fading(guild_id) {
+            drop(handle.pause());
+        }
+    }
+
+    pub fn resume(&self, guild_id: GuildId) {
+        for handle in self.fading(guild_id) {
+            drop(handle.play());
+        }
+    }
+
+    // Mid crossfade, skipping means the song fading out: it is cut off and
+    // the one fading in comes up to its full volume. `false` if nothing has
+    // been handed over yet, so the queue's current track is the one to skip.
+    pub fn skip(&self, guild_id: GuildId) -> bool {
+        let mut guilds = self.guilds.lock().expect("Crossfade lock poisoned");
+        let Some(fades) = guilds.get_mut(&guild_id) else {
+            return false;
+        };
+
+        let mut skipped = false;
+        fades.retain(|fade| {
+            let Some(incoming) = &fade.incoming else {
+                return true;
+            };
+            drop(fade.outgoing.stop());
+            if incoming.faded {
+                drop(incoming.handle.set_volume(incoming.volume));
+            }
+            skipped = true;
+            false
+        });
+        if fades.is_empty() {
+            guilds.remove(&guild_id);
+        }
+
+        skipped
+    }
+
+    // Cuts off whatever is still fading out
+    pub fn stop(&self, guild_id: GuildId) {
+        let fades = {
+            let mut guilds = self.guilds.lock().expect("Crossfade lock poisoned");
+            guilds.remove(&guild_id).unwrap_or_default()
+        };
+        for fade in fades {
+            drop(fade.outgoing.stop());
+        }
+    }
+}
+
+// How long a fade into the next track lasts, and how long the track has left
+// to play, both in output time for a track `position` into the song and
+// playing at `speed` from there. Short tracks fade for at most half their
+// length.
+fn fade_timing(duration: Duration, position: Duration, speed: f64, crossfade: Duration) -> (Duration, Duration) {
+    let length = crossfade.min(duration.div_f64(speed) / 2);
+    (length, duration.saturating_sub(position).div_f64(speed))
+}
+
+// Equal power, so the mix doesn't dip in the middle
+fn fade_gains(progress: f32) -> (f32, f32) {
+    ((progress * FRAC_PI_2).cos(), (progress * FRAC_PI_2).sin())
+}
+
+// What to set the tracks' volumes to, `None` to leave one alone
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Volumes {
+    outgoing: Option<f32>,
+    incoming: Option<f32>,
+}
+
+// The volumes through one fade. A track whose volume someone else changed
+// mid-fade keeps it, it isn't faded any further.
+struct FadeCurve {
+    length: Duration,
+    // What each track plays at outside the fade
+    outgoing: f32,
+    incoming: f32,
+    // What the fade last set, `None` once the track is left alone
+    set: Volumes,
+}
+
+impl FadeCurve {
+    // The incoming track starts silent
+    fn new(length: Duration, outgoing: f32, incoming: f32) -> Self {
+        Self {
+            length,
+            outgoing,
+            incoming,
+            set: Volumes {
+                outgoing: Some(outgoing),
+                incoming: Some(0.0),
+            },
+        }
+    }
+
+    fn progress(&self, elapsed: Duration) -> f32 {
+        (elapsed.as_secs_f32() / self.length.as_secs_f32()).min(1.0)
+    }
+
+    // The volumes `elapsed` into the fade, given what the tracks play at now
+    fn step(&mut self, elapsed: Duration, now: (Option<f32>, f32)) -> Volumes {
+        let (outgoing_gain, incoming_gain) = fade_gains(self.progress(elapsed));
+        self.set = Volumes {
+            outgoing: self
+                .set
+                .outgoing
+                .filter(|&set| now.0 == Some(set))
+                .map(|_| self.outgoing * outgoing_gain),
+            incoming: self
+                .set
+                .incoming
+                .filter(|&set| now.1 == set)
+                .map(|_| self.incoming * incoming_gain),
+        };
+
+        self.set
+    }
+}
+
+// Watches the guild's tracks and starts a crossfade when the playing one is
+// within the guild's `crossfade` setting of its end
+#[derive(Clone)]
+pub struct Crossfader {
+    pub manager: Arc<Songbird>,
+    pub guild_id: GuildId,
+    pub settings: GuildSettingsStore,
+    pub crossfades: Crossfades,
+}
+
+#[async_trait]
+impl VoiceEventHandler for Crossfader {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(track_list) = ctx else {
+            return None;
+        };
+
+        let crossfade = Duration::from_secs(self.settings.get(self.guild_id).await.crossfade);
+        if crossfade.is_zero() {
+            return None;
+        }
+
+        for (state, handle) in *track_list {
+            // A looping track isn't about to end, and streams have no end
+            if !matches!(state.playing, PlayMode::Play)
+                || !matches!(state.loops, LoopState::Finite(0))
+                || self.crossfades.is_fading(self.guild_id, handle)
+            {
+                continue;
+            }
+            let metadata = handle.data::<TrackMetadata>();
+            let Some(duration) = metadata.duration else {
+                continue;
+            };
+
+            // A track the filters couldn't take plays as it is
+            let (position, speed) = metadata.progress.get().unwrap_or((state.position, 1.0));
+            let (length, remaining) = fade_timing(duration, position, speed, crossfade);
+            if length.is_zero() || remaining > length {
+                continue;
+            }
+
+            if self.crossfades.start(self.guild_id, handle) {
+                let crossfader = self.clone();
+                let outgoing = (*handle).clone();
+                tokio::spawn(async move {
+                    crossfader.hand_over(&outgoing, length).await;
+                    crossfader.crossfades.finish(crossfader.guild_id, &outgoing);
+                });
+            }
+        }
+
+        None
+    }
+}
+
+impl Crossfader {
+    async fn hand_over(&self, outgoing: &TrackHandle, length: Duration) {
+        let Some(handler_lock) = self.manager.get(self.guild_id) else {
+            return;
+        };
+
+        let (incoming, outgoing_volume, incoming_volume) = {
+            let handler = handler_lock.lock().await;
+            let queue = handler.queue();
+
+            // `skip` or `stop` may have got here first. With nothing queued
+            // the track plays out, and autoplay can take over.
+            let tracks = queue.current_queue();
+            let (Some(current), Some(incoming)) = (tracks.first(), tracks.get(1)) else {
+                return;
+            };
+            if current.uuid() != outgoing.uuid() {
+                return;
+            }
+            let (Ok(outgoing_state), Ok(incoming_state)) =
+                (outgoing.get_info().await, incoming.get_info().await)
+            else {
+                return;
+            };
+
+            // The queue moves on once its current track is gone from it; the
+            // track itself keeps playing
+            drop(queue.modify_queue(|tracks| tracks.pop_front()));
+            drop(incoming.set_volume(0.0));
+            drop(queue.resume());
+            self.crossfades
+                .handed_over(self.guild_id, outgoing, incoming, incoming_state.volume);
+
+            (incoming.clone(), outgoing_state.volume, incoming_state.volume)
+        };
+
+        info!(
+            "Crossfading in guild {:?} over {}s",
+            self.guild_id,
+            length.as_secs_f32()
+        );
+
+        // Timed by the incoming track, so pausing both holds the fade and it
+        // still finishes if the outgoing one ends a little early
+        let mut curve = FadeCurve::new(length, outgoing_volume, incoming_volume);
+        loop {
+            tokio::time::sleep(FADE_STEP).await;
+
+            let Ok(state) = incoming.get_info().await else {
+                debug!("Crossfade in guild {:?}: incoming track gone", self.guild_id);
+                break;
+            };
+            let outgoing_now = outgoing.get_info().await.ok().map(|state| state.volume);
+
+            let volumes = curve.step(state.play_time, (outgoing_now, state.volume));
+            if !self.crossfades.apply(self.guild_id, outgoing, volumes) {
+                debug!("Crossfade in guild {:?}: ended early", self.guild_id);
+                return;
+            }
+            if curve.progress(state.play_time) >= 1.0 {
+                break;
+            }
+        }
+
+        drop(outgoing.stop());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::id::UserId;
+    use songbird::input::File;
+    use songbird::Call;
+
+    use super::*;
+
+    #[test]
+    fn fade_timing_follows_speed_and_length() {
+        let crossfade = Duration::from_secs(6);
+        let song = Duration::from_secs(200);
+
+        let (length, remaining) = fade_timing(song, Duration::from_secs(150), 1.0, crossfade);
+        assert_eq!((length, remaining), (crossfade, Duration::from_secs(50)));
+
+        // The rest of the song at double speed takes half the time, with the
+        // same fade, however it was played up to here
+        let (length, remaining) = fade_timing(song, Duration::from_secs(150), 2.0, crossfade);
+        assert_eq!((length, remaining), (crossfade, Duration::from_secs(25)));
+
+        // Halfway through the song isn't near the end at any speed
+        let (length, remaining) = fade_timing(song, Duration::from_secs(100), 2.0, crossfade);
+        assert!(remaining > length);
+
+        // A short track fades for half of it
+        let (length, remaining) = fade_timing(Duration::from_secs(8), Duration::ZERO, 1.0, crossfade);
+        assert_eq!((length, remaining), (Duration::from_secs(4), Duration::from_secs(8)));
+    }
+
+    #[test]
+    fn fade_keeps_equal_power() {
+        assert_eq!(fade_gains(0.0), (1.0, 0.0));
+        let (outgoing, incoming) = fade_gains(1.0);
+        assert!(outgoing.abs() < 1e-6 && (incoming - 1.0).abs() < 1e-6);
+
+        for step in 0..=10 {
+            let (outgoing, incoming) = fade_gains(step as f32 / 10.0);
+            assert!((outgoing * outgoing + incoming * incoming - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn fade_curve_runs_from_one_track_to_the_other() {
+        let mut curve = FadeCurve::new(Duration::from_secs(4), 0.8, 0.5);
+        let mut now = (Some(0.8), 0.0);
+
+        for elapsed in [1, 2, 3] {
+            let volumes = curve.step(Duration::from_secs(elapsed), now);
+            let (outgoing, incoming) = (volumes.outgoing.unwrap(), volumes.incoming.unwrap());
+            assert!(outgoing < now.0.unwrap() && incoming > now.1);
+            now = (Some(outgoing), incoming);
+        }
+
+        // Done once the incoming track has played for the whole fade, and
+        // it doesn't go past
+        let volumes = curve.step(Duration::from_secs(5), now);
+        assert_eq!(curve.progress(Duration::from_secs(5)), 1.0);
+        assert!(volumes.outgoing.unwrap().abs() < 1e-6);
+        assert!((volumes.incoming.unwrap() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fade_curve_leaves_changed_volumes_alone() {
+        let mut curve = FadeCurve::new(Duration::from_secs(4), 1.0, 1.0);
+        let volumes = curve.step(Duration::from_secs(1), (Some(1.0), 0.0));
+
+        // Someone turned the incoming track up
+        let volumes = curve.step(Duration::from_secs(2), (volumes.outgoing, 0.9));
+        assert!(volumes.outgoing.is_some());
+        assert_eq!(volumes.incoming, None);
+        let volumes = curve.step(Duration::from_secs(3), (volumes.outgoing, 0.9));
+        assert_eq!(volumes.incoming, None);
+
+        // And the outgoing one down
+        let volumes = curve.step(Duration::from_secs(4), (Some(0.1), 0.9));
+        assert_eq!(volumes, Volumes { outgoing: None, incoming: None });
+    }
+
+    #[tokio::test]
+    async fn skip_only_ends_fades_that_were_handed_over() {
+        let guild_id = GuildId::new(1);
+        let mut call = Call::standalone(guild_id, UserId::new(2));
+        let outgoing = call.enqueue_input(File::new("outgoing.flac").into()).await;
+        let incoming = call.enqueue_input(File::new("incoming.flac").into()).await;
+        let crossfades = Crossfades::default();
+
+        assert!(crossfades.start(guild_id, &outgoing));
+        assert!(!crossfades.start(guild_id, &outgoing));
+
+        // Still the queue's current track, which is for the queue to skip
+        assert!(!crossfades.skip(guild_id));
+        assert!(crossfades.is_fading(guild_id, &outgoing));
+
+        crossfades.handed_over(guild_id, &outgoing, &incoming, 1.0);
+        assert!(crossfades.skip(guild_id));
+        assert!(!crossfades.is_fading(guild_id, &outgoing));
+        assert!(crossfades.fading(guild_id).is_empty());
+
+        // The fade sees it was ended and stops setting volumes
+        let volumes = Volumes {
+            outgoing: Some(0.5),
+            incoming: Some(0.5),
+        };
+        assert!(!crossfades.apply(guild_id, &outgoing, volumes));
+    }
+}
@@ -6,6 +6,7 @@ use serenity::model::Timestamp;
 use serenity::prelude::*;
 use songbird::tracks::TrackHandle;
 
+use crate::commands::music::metadata::TrackMetadata;
 use crate::commands::utils::{send_warning, to_time};
 
 #[command]
@@ -48,12 +49,14 @@ async fn display_track_info(
     track: &TrackHandle,
 ) -> CommandResult {
     let track_info = track.get_info().await.unwrap();
+    let metadata = track.data::<TrackMetadata>();
 
     let time_formatted = to_time(track_info.position.as_secs());
 
     let embed = CreateEmbed::default()
         .color(0xffffff)
         .title("Now Playing")
+        .description(metadata.link())
         .field("Position", &time_formatted, true)
         .field("Status", format!("{:?}", track_info.playing), true)
         .timestamp(Timestamp::now());
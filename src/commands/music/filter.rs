@@ -0,0 +1,93 @@
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tracing::info;
+
+use crate::commands::utils::{
+    get_filters, get_guild_id_from_message, get_prefix, send_success_message, send_warning,
+};
+use crate::filters::{EqBand, FilterPreset, FilterSettings, MAX_EQ_BANDS, PRESETS};
+
+fn preset_keys() -> String {
+    PRESETS
+        .iter()
+        .map(|preset| format!("`{}`", preset.key()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn describe(settings: &FilterSettings) -> String {
+    if settings.is_empty() {
+        return ":control_knobs: No filters active.".to_string();
+    }
+
+    format!(":control_knobs: Filters: {}", settings.describe().join(" · "))
+}
+
+// `filter` shows the active filters, `filter <preset>` turns one on or off,
+// `filter eq <hz>:<db> ...` sets the custom equalizer and `filter off` clears
+// everything. Changes are heard right away on the current track.
+#[command]
+#[only_in(guilds)]
+async fn filter(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+    let filters = get_filters(ctx, guild_id).await;
+
+    let mut words = args.rest().split_whitespace();
+    let Some(name) = words.next().map(str::to_ascii_lowercase) else {
+        let prefix = get_prefix(ctx, msg).await;
+        send_success_message(ctx, msg, &describe(&filters.get())).await?;
+        send_warning(
+            ctx,
+            msg,
+            &format!(
+                "Toggle one with `{0}filter <name>` ({1}), set bands with `{0}filter eq <hz>:<db> ...` or clear them with `{0}filter off`.",
+                prefix,
+                preset_keys()
+            ),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let settings = match name.as_str() {
+        "off" | "clear" | "reset" => filters.update(FilterSettings::clear),
+        "eq" | "equalizer" => {
+            let bands = match words.map(EqBand::parse).collect::<Result<Vec<_>, _>>() {
+                Ok(bands) if bands.len() <= MAX_EQ_BANDS => bands,
+                Ok(_) => {
+                    send_warning(ctx, msg, &format!("At most {} bands can be set.", MAX_EQ_BANDS)).await?;
+                    return Ok(());
+                }
+                Err(reason) => {
+                    send_warning(ctx, msg, &reason).await?;
+                    return Ok(());
+                }
+            };
+            filters.update(|settings| settings.equalizer = bands)
+        }
+        key => match FilterPreset::from_key(key) {
+            Some(preset) => filters.update(|settings| {
+                settings.toggle(preset);
+            }),
+            None => {
+                send_warning(
+                    ctx,
+                    msg,
+                    &format!("Unknown filter `{}`. Available: {}, `eq`, `off`", key, preset_keys()),
+                )
+                .await?;
+                return Ok(());
+            }
+        },
+    };
+
+    info!(
+        "filter: {} set filters in guild {:?} to {:?}",
+        msg.author.name, guild_id, settings
+    );
+    send_success_message(ctx, msg, &describe(&settings)).await?;
+
+    Ok(())
+}
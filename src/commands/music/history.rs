@@ -0,0 +1,149 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::prelude::*;
+use serenity::model::Timestamp;
+use serenity::{async_trait, prelude::*};
+use songbird::events::{Event, EventContext, EventHandler as VoiceEventHandler};
+use tracing::debug;
+
+use crate::commands::music::metadata::TrackMetadata;
+use crate::commands::utils::{get_guild_id_from_message, send_warning, to_time};
+use crate::TrackHistoryKey;
+
+// Number of finished tracks remembered per guild
+pub const HISTORY_LIMIT: usize = 50;
+
+// Number of entries shown by the history command
+const HISTORY_PAGE_SIZE: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct PlayedTrack {
+    pub metadata: TrackMetadata,
+    pub finished_at: Instant,
+}
+
+#[derive(Clone, Default)]
+pub struct TrackHistory {
+    inner: Arc<RwLock<HashMap<GuildId, VecDeque<PlayedTrack>>>>,
+}
+
+impl TrackHistory {
+    pub async fn push(&self, guild_id: GuildId, metadata: TrackMetadata) {
+        let mut inner = self.inner.write().await;
+        let entries = inner.entry(guild_id).or_default();
+
+        entries.push_front(PlayedTrack {
+            metadata,
+            finished_at: Instant::now(),
+        });
+        entries.truncate(HISTORY_LIMIT);
+    }
+
+    pub async fn pop_latest(&self, guild_id: GuildId) -> Option<TrackMetadata> {
+        let mut inner = self.inner.write().await;
+
+        inner
+            .get_mut(&guild_id)
+            .and_then(|entries| entries.pop_front())
+            .map(|played| played.metadata)
+    }
+
+    // Most recently finished tracks first
+    pub async fn recent(&self, guild_id: GuildId, limit: usize) -> Vec<PlayedTrack> {
+        let inner = self.inner.read().await;
+
+        inner
+            .get(&guild_id)
+            .map(|entries| entries.iter().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+pub struct HistoryRecorder {
+    pub history: TrackHistory,
+    pub guild_id: GuildId,
+}
+
+#[async_trait]
+impl VoiceEventHandler for HistoryRecorder {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(track_list) = ctx {
+            for (state, handle) in *track_list {
+                // Tracks dropped from the queue by `stop` / `clear` never played
+                if state.play_time.is_zero() {
+                    continue;
+                }
+
+                let metadata = handle.data::<TrackMetadata>();
+                debug!(
+                    "HistoryRecorder: '{}' finished in guild {:?}",
+                    metadata.title, self.guild_id
+                );
+                self.history
+                    .push(self.guild_id, TrackMetadata::clone(&metadata))
+                    .await;
+            }
+        }
+
+        None
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+async fn history(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let history = {
+        let data = ctx.data.read().await;
+        data.get::<TrackHistoryKey>()
+            .cloned()
+            .expect("Should exist in typemap")
+    };
+
+    let entries = history.recent(guild_id, HISTORY_PAGE_SIZE).await;
+
+    if entries.is_empty() {
+        send_warning(ctx, msg, "Nothing has been played yet.").await?;
+        return Ok(());
+    }
+
+    let formatter = timeago::Formatter::new();
+    let description = entries
+        .iter()
+        .enumerate()
+        .map(|(idx, played)| {
+            let duration = played
+                .metadata
+                .duration
+                .map(|d| to_time(d.as_secs()))
+                .unwrap_or_else(|| "live".to_string());
+
+            format!(
+                "`{}.` {} `{}` - requested by {} ({})",
+                idx + 1,
+                played.metadata.link(),
+                duration,
+                played.metadata.requester_name,
+                formatter.convert(played.finished_at.elapsed())
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title("Recently Played")
+        .description(description)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateMessage::default().add_embed(embed);
+    msg.channel_id.send_message(&ctx.http, builder).await?;
+
+    Ok(())
+}
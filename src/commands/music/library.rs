@@ -0,0 +1,132 @@
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::model::Timestamp;
+use serenity::prelude::*;
+use tracing::warn;
+
+use crate::commands::utils::{
+    get_prefix, send_error_message, send_success_message, send_warning, to_time,
+};
+use crate::library::Library;
+use crate::LibraryKey;
+
+// Number of matches listed by `library search`
+const SEARCH_RESULTS_SHOWN: usize = 10;
+
+// The library only exists when `library_path` is configured
+async fn get_library(ctx: &Context) -> Option<Library> {
+    let data = ctx.data.read().await;
+    data.get::<LibraryKey>().cloned()
+}
+
+#[command]
+#[aliases(lib)]
+#[only_in(guilds)]
+#[sub_commands(library_search, library_rescan)]
+async fn library(ctx: &Context, msg: &Message) -> CommandResult {
+    let Some(library) = get_library(ctx).await else {
+        send_warning(ctx, msg, "The local library is not enabled.").await?;
+        return Ok(());
+    };
+
+    let prefix = get_prefix(ctx, msg).await;
+    let count = library.count().await?;
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(format!(":file_cabinet: Local library ({} tracks)", count))
+        .description(format!(
+            "`{0}library search <query>` - find tracks\n\
+             `{0}play local:<query>` - play the best match\n\
+             `{0}library rescan` - pick up new and changed files",
+            prefix
+        ))
+        .timestamp(Timestamp::now());
+    msg.channel_id
+        .send_message(&ctx.http, CreateMessage::default().add_embed(embed))
+        .await?;
+
+    Ok(())
+}
+
+#[command("search")]
+#[only_in(guilds)]
+async fn library_search(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let Some(library) = get_library(ctx).await else {
+        send_warning(ctx, msg, "The local library is not enabled.").await?;
+        return Ok(());
+    };
+
+    let query = args.rest().trim();
+    if query.is_empty() {
+        send_warning(ctx, msg, "Use the command like this: library search <query>").await?;
+        return Ok(());
+    }
+
+    let tracks = library.search(query, SEARCH_RESULTS_SHOWN).await?;
+    if tracks.is_empty() {
+        send_warning(ctx, msg, &format!("Nothing in the library matches: {}", query)).await?;
+        return Ok(());
+    }
+
+    let lines: Vec<String> = tracks
+        .iter()
+        .enumerate()
+        .map(|(idx, track)| {
+            let duration = track
+                .duration
+                .map(|d| to_time(d.as_secs()))
+                .unwrap_or_else(|| "?".to_string());
+            let album = track
+                .album
+                .as_ref()
+                .map(|album| format!(" - *{}*", album))
+                .unwrap_or_default();
+
+            format!("`{}.` **{}** `{}`{}", idx + 1, track.display_title(), duration, album)
+        })
+        .collect();
+
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(format!(":mag: Library results for: {}", query))
+        .description(lines.join("\n"))
+        .timestamp(Timestamp::now());
+    msg.channel_id
+        .send_message(&ctx.http, CreateMessage::default().add_embed(embed))
+        .await?;
+
+    Ok(())
+}
+
+#[command("rescan")]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_GUILD)]
+async fn library_rescan(ctx: &Context, msg: &Message) -> CommandResult {
+    let Some(library) = get_library(ctx).await else {
+        send_warning(ctx, msg, "The local library is not enabled.").await?;
+        return Ok(());
+    };
+
+    send_success_message(ctx, msg, ":file_cabinet: Scanning the library...").await?;
+
+    match library.scan().await {
+        Some(Ok(report)) => {
+            send_success_message(
+                ctx,
+                msg,
+                &format!(
+                    ":file_cabinet: Library scanned: {} tracks, {} new or changed, {} removed, {} unreadable.",
+                    report.total, report.updated, report.removed, report.failed
+                ),
+            )
+            .await
+        }
+        Some(Err(e)) => {
+            warn!("library_rescan: Scan failed: {}", e);
+            send_error_message(ctx, msg, "Scanning the library failed.").await
+        }
+        None => send_warning(ctx, msg, "The library is already being scanned.").await,
+    }
+}
@@ -0,0 +1,700 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::header::ACCEPT_LANGUAGE;
+use reqwest::{Client as HttpClient, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::task::{JoinError, JoinSet};
+use tracing::{debug, warn};
+
+use crate::commands::music::search::SearchProvider;
+use crate::ytdlp::{FlatEntry, YtDlp, YtDlpError};
+
+// Spotify, Apple Music and Deezer links, which yt-dlp can't play. The titles
+// and artists behind a link are read from `link_metadata_url` if one is
+// configured, otherwise from the metadata the pages embed for link previews
+// (Deezer from its public API), and each track is then searched on YouTube.
+
+// How long a page or the metadata endpoint may take, body included
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Most tracks taken from one album or playlist. They are all looked up before
+// any is queued, so more would keep the member waiting too long.
+pub const MAX_LINKED_TRACKS: usize = 25;
+
+// YouTube results compared for each track
+const SEARCH_CANDIDATES: usize = 3;
+
+// Track pages of a Spotify album or playlist read at once
+const SONG_PAGE_FETCHES: usize = 4;
+
+const DEEZER_API: &str = "https://api.deezer.com";
+
+lazy_static! {
+    static ref SPOTIFY_LINK: Regex = Regex::new(
+        r"^https?://open\.spotify\.com/(?:intl-[a-z]{2}(?:-[a-zA-Z]+)?/)?(track|album|playlist)/([A-Za-z0-9]+)"
+    )
+    .unwrap();
+    static ref APPLE_MUSIC_LINK: Regex =
+        Regex::new(r"^https?://(?:geo\.)?music\.apple\.com/[a-z]{2}/(song|album|playlist)/").unwrap();
+    static ref DEEZER_LINK: Regex =
+        Regex::new(r"^https?://(?:www\.)?deezer\.com/(?:[a-z]{2}/)?(track|album|playlist)/(\d+)").unwrap();
+    static ref META_TAG: Regex = Regex::new(r"(?i)<meta\b[^>]*>").unwrap();
+    static ref ATTRIBUTE: Regex = Regex::new(r#"([A-Za-z:_-]+)\s*=\s*"([^"]*)""#).unwrap();
+    static ref JSON_LD: Regex =
+        Regex::new(r#"(?is)<script[^>]*type="application/ld\+json"[^>]*>(.*?)</script>"#).unwrap();
+    static ref ISO_DURATION: Regex =
+        Regex::new(r"^PT(?:(\d+)H)?(?:(\d+)M)?(?:(\d+(?:\.\d+)?)S)?$").unwrap();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkService {
+    Spotify,
+    AppleMusic,
+    Deezer,
+}
+
+impl LinkService {
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkService::Spotify => "Spotify",
+            LinkService::AppleMusic => "Apple Music",
+            LinkService::Deezer => "Deezer",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkKind {
+    Track,
+    Album,
+    Playlist,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct StreamingLink {
+    pub service: LinkService,
+    pub kind: LinkKind,
+    pub url: String,
+    // The Deezer ID, or the track an Apple Music album link points at
+    id: Option<String>,
+}
+
+impl StreamingLink {
+    pub fn parse(url: &str) -> Option<Self> {
+        let (service, captures) = [
+            (LinkService::Spotify, &*SPOTIFY_LINK),
+            (LinkService::AppleMusic, &*APPLE_MUSIC_LINK),
+            (LinkService::Deezer, &*DEEZER_LINK),
+        ]
+        .into_iter()
+        .find_map(|(service, pattern)| Some((service, pattern.captures(url)?)))?;
+
+        let mut kind = match &captures[1] {
+            "track" | "song" => LinkKind::Track,
+            "album" => LinkKind::Album,
+            _ => LinkKind::Playlist,
+        };
+        let mut id = captures.get(2).map(|id| id.as_str().to_string());
+
+        // Apple Music links a song as its album with `?i=<track id>`
+        if service == LinkService::AppleMusic {
+            id = Url::parse(url)
+                .ok()?
+                .query_pairs()
+                .find(|(key, _)| key == "i")
+                .map(|(_, track)| track.into_owned());
+            if id.is_some() {
+                kind = LinkKind::Track;
+            }
+        }
+
+        Some(Self {
+            service,
+            kind,
+            url: url.to_string(),
+            id,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkedTrack {
+    pub title: String,
+    pub artist: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl LinkedTrack {
+    // Shown in the queue, and what YouTube is searched for
+    pub fn display_title(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("{} - {}", artist, self.title),
+            None => self.title.clone(),
+        }
+    }
+}
+
+// What `link_metadata_url` answers for `?url=<link>`
+#[derive(Deserialize)]
+struct EndpointResponse {
+    tracks: Vec<EndpointTrack>,
+}
+
+#[derive(Deserialize)]
+struct EndpointTrack {
+    title: String,
+    artist: Option<String>,
+    // Seconds
+    duration: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct DeezerTracks {
+    data: Vec<DeezerTrack>,
+}
+
+#[derive(Deserialize)]
+struct DeezerTrack {
+    title: String,
+    // Seconds
+    duration: Option<u64>,
+    artist: Option<DeezerArtist>,
+}
+
+#[derive(Deserialize)]
+struct DeezerArtist {
+    name: String,
+}
+
+impl From<DeezerTrack> for LinkedTrack {
+    fn from(track: DeezerTrack) -> Self {
+        Self {
+            title: track.title,
+            artist: track.artist.map(|artist| artist.name),
+            duration: track.duration.filter(|secs| *secs > 0).map(Duration::from_secs),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LinkMetadata {
+    http_client: HttpClient,
+    endpoint: Option<String>,
+    deezer_api: String,
+}
+
+impl LinkMetadata {
+    pub fn new(http_client: HttpClient, endpoint: Option<String>) -> Self {
+        Self {
+            http_client,
+            endpoint,
+            deezer_api: DEEZER_API.to_string(),
+        }
+    }
+
+    // The tracks behind a link, at most `limit` of an album or playlist
+    pub async fn tracks(&self, link: &StreamingLink, limit: usize) -> Result<Vec<LinkedTrack>, String> {
+        let mut tracks = match (&self.endpoint, link.service) {
+            (Some(endpoint), _) => self.ask_endpoint(endpoint, link).await?,
+            (None, LinkService::Deezer) => self.ask_deezer(link, limit).await?,
+            (None, _) => self.read_page(link, limit).await?,
+        };
+
+        tracks.truncate(if link.kind == LinkKind::Track { 1 } else { limit });
+        Ok(tracks)
+    }
+
+    async fn fetch(&self, url: &str) -> Result<String, String> {
+        let response = self
+            .http_client
+            .get(url)
+            .header(ACCEPT_LANGUAGE, "en")
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("request to {} failed: {}", url, e))?;
+
+        response
+            .text()
+            .await
+            .map_err(|e| format!("reading {} failed: {}", url, e))
+    }
+
+    async fn fetch_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        let body = self.fetch(url).await?;
+        serde_json::from_str(&body).map_err(|e| format!("unexpected answer from {}: {}", url, e))
+    }
+
+    async fn ask_endpoint(&self, endpoint: &str, link: &StreamingLink) -> Result<Vec<LinkedTrack>, String> {
+        let url = Url::parse_with_params(endpoint, &[("url", &link.url)]).map_err(|e| e.to_string())?;
+        let response: EndpointResponse = self.fetch_json(url.as_str()).await?;
+
+        Ok(response
+            .tracks
+            .into_iter()
+            .map(|track| LinkedTrack {
+                title: track.title,
+                artist: track.artist.filter(|artist| !artist.is_empty()),
+                duration: track
+                    .duration
+                    .filter(|secs| *secs > 0.0)
+                    .map(Duration::from_secs_f64),
+            })
+            .collect())
+    }
+
+    async fn ask_deezer(&self, link: &StreamingLink, limit: usize) -> Result<Vec<LinkedTrack>, String> {
+        let id = link.id.as_deref().ok_or("Deezer link without an ID")?;
+        let tracks = match link.kind {
+            LinkKind::Track => {
+                let track: DeezerTrack = self.fetch_json(&format!("{}/track/{}", self.deezer_api, id)).await?;
+                vec![track]
+            }
+            LinkKind::Album | LinkKind::Playlist => {
+                let kind = if link.kind == LinkKind::Album { "album" } else { "playlist" };
+                let url = format!("{}/{}/{}/tracks?limit={}", self.deezer_api, kind, id, limit);
+                self.fetch_json::<DeezerTracks>(&url).await?.data
+            }
+        };
+
+        Ok(tracks.into_iter().map(LinkedTrack::from).collect())
+    }
+
+    // Apple Music describes its pages with schema.org data, Spotify with Open
+    // Graph tags where an album or playlist only links its tracks' pages
+    async fn read_page(&self, link: &StreamingLink, limit: usize) -> Result<Vec<LinkedTrack>, String> {
+        let page = self.fetch(&link.url).await?;
+
+        let mut recordings = json_ld_tracks(&page);
+        if !recordings.is_empty() {
+            if let (LinkKind::Track, Some(id)) = (link.kind, &link.id) {
+                let marker = format!("i={}", id);
+                if let Some(idx) = recordings
+                    .iter()
+                    .position(|(url, _)| url.as_deref().is_some_and(|url| url.contains(&marker)))
+                {
+                    recordings.swap(0, idx);
+                }
+            }
+            return Ok(recordings.into_iter().map(|(_, track)| track).collect());
+        }
+
+        let tags = meta_tags(&page);
+        if link.kind == LinkKind::Track {
+            return Ok(track_from_meta(&tags).into_iter().collect());
+        }
+
+        let song_urls = tags
+            .into_iter()
+            .filter(|(key, _)| key == "music:song")
+            .map(|(_, url)| url)
+            .take(limit);
+
+        // A few at a time, and a page that can't be read only loses its track
+        let mut pages = JoinSet::new();
+        let mut found = Vec::new();
+        for (idx, song_url) in song_urls.enumerate() {
+            if pages.len() >= SONG_PAGE_FETCHES {
+                found.extend(song_page_track(pages.join_next().await));
+            }
+            let links = self.clone();
+            pages.spawn(async move {
+                match links.fetch(&song_url).await {
+                    Ok(page) => track_from_meta(&meta_tags(&page)).map(|track| (idx, track)),
+                    Err(e) => {
+                        debug!("LinkMetadata: Skipping a track page: {}", e);
+                        None
+                    }
+                }
+            });
+        }
+        while let Some(page) = pages.join_next().await {
+            found.extend(song_page_track(Some(page)));
+        }
+        found.sort_by_key(|(idx, _)| *idx);
+
+        Ok(found.into_iter().map(|(_, track)| track).collect())
+    }
+}
+
+fn song_page_track(page: Option<Result<Option<(usize, LinkedTrack)>, JoinError>>) -> Option<(usize, LinkedTrack)> {
+    match page? {
+        Ok(track) => track,
+        Err(e) => {
+            warn!("LinkMetadata: Track page task failed: {}", e);
+            None
+        }
+    }
+}
+
+// The YouTube result for a track: of the first few, the one closest in
+// length to the original, as the top result is often a music video with an
+// intro or a live version. `None` if nothing was found.
+pub async fn find_on_youtube(
+    ytdlp: &YtDlp,
+    track: &LinkedTrack,
+) -> Result<Option<(String, Option<Duration>)>, YtDlpError> {
+    let search = SearchProvider::YouTube
+        .search_query(&track.display_title(), SEARCH_CANDIDATES)
+        .expect("YouTube can be searched");
+    let entries = ytdlp.list(&search, SEARCH_CANDIDATES).await?;
+
+    Ok(best_match(entries, track.duration))
+}
+
+fn best_match(entries: Vec<FlatEntry>, duration: Option<Duration>) -> Option<(String, Option<Duration>)> {
+    let mut candidates = entries.into_iter().filter_map(|entry| {
+        let url = entry.url.filter(|url| url.starts_with("http"))?;
+        Some((url, entry.duration.map(Duration::from_secs_f64)))
+    });
+
+    let Some(wanted) = duration else {
+        return candidates.next();
+    };
+
+    // The earlier result wins a tie, results without a length come last
+    candidates.min_by_key(|(_, found)| {
+        found.map_or(u128::MAX, |found| found.abs_diff(wanted).as_millis())
+    })
+}
+
+fn meta_tags(page: &str) -> Vec<(String, String)> {
+    META_TAG
+        .find_iter(page)
+        .filter_map(|tag| {
+            let mut key = None;
+            let mut content = None;
+            for attribute in ATTRIBUTE.captures_iter(tag.as_str()) {
+                match &attribute[1] {
+                    "property" | "name" => key = Some(attribute[2].to_string()),
+                    "content" => content = Some(decode_entities(&attribute[2])),
+                    _ => {}
+                }
+            }
+            Some((key?, content?))
+        })
+        .collect()
+}
+
+fn track_from_meta(tags: &[(String, String)]) -> Option<LinkedTrack> {
+    let tag = |wanted: &str| {
+        tags.iter()
+            .find(|(key, content)| key == wanted && !content.is_empty())
+            .map(|(_, content)| content.clone())
+    };
+
+    Some(LinkedTrack {
+        title: tag("og:title")?,
+        artist: tag("music:musician_description"),
+        duration: tag("music:duration")
+            .and_then(|secs| secs.parse().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs),
+    })
+}
+
+// Every `MusicRecording` in the page's schema.org data, with its URL
+fn json_ld_tracks(page: &str) -> Vec<(Option<String>, LinkedTrack)> {
+    let mut tracks = Vec::new();
+    for block in JSON_LD.captures_iter(page) {
+        if let Ok(value) = serde_json::from_str::<Value>(&block[1]) {
+            collect_recordings(&value, None, &mut tracks);
+        }
+    }
+
+    tracks
+}
+
+// A recording without an artist of its own takes the one of the album or
+// playlist it's part of
+fn collect_recordings<'a>(
+    value: &'a Value,
+    artist: Option<&'a str>,
+    tracks: &mut Vec<(Option<String>, LinkedTrack)>,
+) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                collect_recordings(item, artist, tracks);
+            }
+        }
+        Value::Object(object) => {
+            let artist = object.get("byArtist").and_then(artist_name).or(artist);
+            if object.get("@type").and_then(Value::as_str) == Some("MusicRecording") {
+                if let Some(title) = object.get("name").and_then(Value::as_str) {
+                    let track = LinkedTrack {
+                        title: title.to_string(),
+                        artist: artist.map(str::to_string),
+                        duration: object
+                            .get("duration")
+                            .and_then(Value::as_str)
+                            .and_then(parse_iso_duration),
+                    };
+                    let url = object.get("url").and_then(Value::as_str).map(str::to_string);
+                    tracks.push((url, track));
+                }
+                return;
+            }
+
+            for child in object.values() {
+                collect_recordings(child, artist, tracks);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn artist_name(value: &Value) -> Option<&str> {
+    match value {
+        Value::Array(artists) => artists.first().and_then(artist_name),
+        Value::Object(artist) => artist.get("name")?.as_str(),
+        Value::String(name) => Some(name),
+        _ => None,
+    }
+}
+
+// `PT3M25S`
+fn parse_iso_duration(text: &str) -> Option<Duration> {
+    let captures = ISO_DURATION.captures(text)?;
+    let part = |idx: usize| {
+        captures
+            .get(idx)
+            .map_or(Ok(0.0), |part| part.as_str().parse::<f64>())
+            .ok()
+    };
+    let secs = part(1)? * 3600.0 + part(2)? * 60.0 + part(3)?;
+
+    Some(Duration::from_secs_f64(secs)).filter(|duration| !duration.is_zero())
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // A local stand-in for the services: answers each request with the body
+    // routed to its path, built once the server's address is known
+    async fn serve(routes: impl FnOnce(&str) -> Vec<(&'static str, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let routes = routes(&base);
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = vec![0; 8192];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]);
+                let target = request.split_whitespace().nth(1).unwrap_or("/");
+                let path = target.split('?').next().unwrap_or(target);
+
+                let (status, body) = routes
+                    .iter()
+                    .find(|(route, _)| *route == path)
+                    .map_or(("404 Not Found", ""), |(_, body)| ("200 OK", body.as_str()));
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+
+        base
+    }
+
+    fn link(kind: LinkKind, url: String, id: Option<&str>) -> StreamingLink {
+        StreamingLink {
+            service: LinkService::Spotify,
+            kind,
+            url,
+            id: id.map(str::to_string),
+        }
+    }
+
+    fn spotify_track_page(title: &str, artist: &str, secs: u64) -> String {
+        format!(
+            r#"<html><head><meta property="og:title" content="{}"/>
+            <meta name="music:musician_description" content="{}"/>
+            <meta name="music:duration" content="{}"/></head></html>"#,
+            title, artist, secs
+        )
+    }
+
+    fn linked(title: &str, artist: &str, secs: u64) -> LinkedTrack {
+        LinkedTrack {
+            title: title.to_string(),
+            artist: Some(artist.to_string()),
+            duration: Some(Duration::from_secs(secs)),
+        }
+    }
+
+    #[test]
+    fn recognises_streaming_links() {
+        let spotify = StreamingLink::parse("https://open.spotify.com/intl-de/track/4uLU6hMCjMI75M1A2tKUQC?si=x").unwrap();
+        assert_eq!((spotify.service, spotify.kind), (LinkService::Spotify, LinkKind::Track));
+
+        let apple = StreamingLink::parse("https://music.apple.com/us/album/discovery/697194953?i=697195462").unwrap();
+        assert_eq!((apple.service, apple.kind), (LinkService::AppleMusic, LinkKind::Track));
+        assert_eq!(apple.id.as_deref(), Some("697195462"));
+
+        let album = StreamingLink::parse("https://music.apple.com/us/album/discovery/697194953").unwrap();
+        assert_eq!(album.kind, LinkKind::Album);
+
+        let deezer = StreamingLink::parse("https://www.deezer.com/en/playlist/908622995").unwrap();
+        assert_eq!((deezer.service, deezer.kind), (LinkService::Deezer, LinkKind::Playlist));
+        assert_eq!(deezer.id.as_deref(), Some("908622995"));
+
+        assert!(StreamingLink::parse("https://www.youtube.com/watch?v=dQw4w9WgXcQ").is_none());
+        assert!(StreamingLink::parse("https://open.spotify.com/artist/4tZwfgrHOc3mvqYlEYSvVi").is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_tracks_from_the_metadata_endpoint() {
+        let base = serve(|_| {
+            vec![(
+                "/lookup",
+                r#"{"tracks": [{"title": "One More Time", "artist": "Daft Punk", "duration": 320.4},
+                               {"title": "Aerodynamic", "artist": "Daft Punk"}]}"#
+                    .to_string(),
+            )]
+        })
+        .await;
+        let links = LinkMetadata::new(HttpClient::new(), Some(format!("{}/lookup", base)));
+
+        let album = link(LinkKind::Album, "https://open.spotify.com/album/2noRn2Aes5aoNVsU6iWThc".to_string(), None);
+        let tracks = links.tracks(&album, 10).await.unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].display_title(), "Daft Punk - One More Time");
+        assert_eq!(tracks[1].duration, None);
+
+        let single = link(LinkKind::Track, "https://open.spotify.com/track/0DiWol3AO6WpXZgp0goxAV".to_string(), None);
+        assert_eq!(links.tracks(&single, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn follows_the_track_pages_of_a_playlist() {
+        let base = serve(|base| {
+            vec![
+                (
+                    "/playlist/1",
+                    format!(
+                        r#"<meta property="og:title" content="Road Trip"/>
+                        <meta name="music:song" content="{0}/track/a"/>
+                        <meta name="music:song" content="{0}/track/b"/>
+                        <meta name="music:song" content="{0}/track/c"/>"#,
+                        base
+                    ),
+                ),
+                ("/track/a", spotify_track_page("Harder, Better, Faster, Stronger", "Daft Punk", 224)),
+                ("/track/b", spotify_track_page("Tom&#39;s Diner", "Suzanne Vega", 129)),
+                ("/track/c", spotify_track_page("Unused", "Nobody", 1)),
+            ]
+        })
+        .await;
+        let links = LinkMetadata::new(HttpClient::new(), None);
+
+        let playlist = link(LinkKind::Playlist, format!("{}/playlist/1", base), None);
+        let tracks = links.tracks(&playlist, 2).await.unwrap();
+        assert_eq!(
+            tracks,
+            vec![
+                linked("Harder, Better, Faster, Stronger", "Daft Punk", 224),
+                linked("Tom's Diner", "Suzanne Vega", 129),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_structured_data_and_picks_the_linked_track() {
+        let base = serve(|_| {
+            vec![(
+                "/us/album/discovery/1",
+                r#"<script type="application/ld+json">{"@type": "MusicAlbum", "name": "Discovery",
+                    "byArtist": [{"@type": "MusicGroup", "name": "Daft Punk"}],
+                    "tracks": [
+                        {"@type": "MusicRecording", "name": "One More Time", "duration": "PT5M20S",
+                         "url": "https://music.apple.com/us/song/one-more-time/11"},
+                        {"@type": "MusicRecording", "name": "Digital Love", "duration": "PT4M58S",
+                         "url": "https://music.apple.com/us/album/discovery/1?i=13"}
+                    ]}</script>"#
+                    .to_string(),
+            )]
+        })
+        .await;
+        let links = LinkMetadata::new(HttpClient::new(), None);
+
+        let album = link(LinkKind::Album, format!("{}/us/album/discovery/1", base), None);
+        assert_eq!(links.tracks(&album, 10).await.unwrap().len(), 2);
+
+        let song = link(LinkKind::Track, format!("{}/us/album/discovery/1?i=13", base), Some("13"));
+        assert_eq!(
+            links.tracks(&song, 10).await.unwrap(),
+            vec![linked("Digital Love", "Daft Punk", 298)]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_deezer_from_its_api() {
+        let base = serve(|_| {
+            vec![(
+                "/playlist/5/tracks",
+                r#"{"data": [{"title": "Around the World", "duration": 429, "artist": {"name": "Daft Punk"}}]}"#
+                    .to_string(),
+            )]
+        })
+        .await;
+        let links = LinkMetadata {
+            deezer_api: base,
+            ..LinkMetadata::new(HttpClient::new(), None)
+        };
+
+        let playlist = StreamingLink::parse("https://www.deezer.com/playlist/5").unwrap();
+        assert_eq!(
+            links.tracks(&playlist, 10).await.unwrap(),
+            vec![linked("Around the World", "Daft Punk", 429)]
+        );
+
+        let missing = StreamingLink::parse("https://www.deezer.com/track/6").unwrap();
+        assert!(links.tracks(&missing, 10).await.is_err());
+    }
+
+    #[test]
+    fn prefers_results_close_to_the_original_length() {
+        let entry = |url: &str, secs: Option<f64>| FlatEntry {
+            url: Some(url.to_string()),
+            title: None,
+            duration: secs,
+        };
+        let results = || {
+            vec![
+                entry("https://youtu.be/video", Some(412.0)),
+                entry("https://youtu.be/audio", Some(321.0)),
+                entry("https://youtu.be/live", None),
+            ]
+        };
+
+        let best = best_match(results(), Some(Duration::from_secs(320)));
+        assert_eq!(best.unwrap().0, "https://youtu.be/audio");
+
+        let first = best_match(results(), None);
+        assert_eq!(first.unwrap().0, "https://youtu.be/video");
+    }
+}
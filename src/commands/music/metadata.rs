@@ -0,0 +1,112 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::id::UserId;
+use songbird::input::{File, HttpRequest, Input};
+
+use crate::filters::PlaybackProgress;
+use crate::ytdlp::{TrackInfo, YtDlp};
+
+// Information attached to every track we enqueue, readable from any
+// `TrackHandle` via `handle.data::<TrackMetadata>()`.
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackMetadata {
+    pub title: String,
+    pub url: String,
+    pub duration: Option<Duration>,
+    pub requester_id: UserId,
+    pub requester_name: String,
+    // Picked by autoplay rather than queued by a member
+    pub autoplay: bool,
+    #[serde(default)]
+    pub source: TrackSource,
+    // Song currently announced by an internet radio station
+    #[serde(skip)]
+    pub stream_title: StreamTitle,
+    // How far the filters have played it
+    #[serde(skip)]
+    pub progress: PlaybackProgress,
+}
+
+// How `url` is turned back into audio when the track is queued again
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrackSource {
+    #[default]
+    YoutubeDl,
+    // A plain audio file or stream, e.g. a Discord attachment or a radio station
+    Http,
+    // A file from the local library, `url` being its path
+    File,
+}
+
+// Shared between every clone of the metadata, so the station's title can be
+// updated while the track sits in the queue
+#[derive(Clone, Debug, Default)]
+pub struct StreamTitle(Arc<RwLock<Option<String>>>);
+
+impl StreamTitle {
+    pub fn get(&self) -> Option<String> {
+        self.0.read().expect("Stream title lock poisoned").clone()
+    }
+
+    pub fn set(&self, title: Option<String>) {
+        *self.0.write().expect("Stream title lock poisoned") = title;
+    }
+}
+
+impl TrackMetadata {
+    pub fn from_info(
+        info: &TrackInfo,
+        fallback_url: &str,
+        requester_id: UserId,
+        requester_name: &str,
+    ) -> Self {
+        Self {
+            title: info
+                .title
+                .clone()
+                .or_else(|| info.track.clone())
+                .unwrap_or_else(|| fallback_url.to_string()),
+            url: info
+                .webpage_url
+                .clone()
+                .unwrap_or_else(|| fallback_url.to_string()),
+            duration: info
+                .duration
+                .filter(|duration| duration.is_finite() && *duration >= 0.0)
+                .map(Duration::from_secs_f64),
+            requester_id,
+            requester_name: requester_name.to_string(),
+            autoplay: false,
+            source: TrackSource::YoutubeDl,
+            stream_title: StreamTitle::default(),
+            progress: PlaybackProgress::default(),
+        }
+    }
+
+    // The title with the song an internet radio station is playing, if any
+    pub fn display_title(&self) -> String {
+        match self.stream_title.get() {
+            Some(song) => format!("{} ({})", song, self.title),
+            None => self.title.clone(),
+        }
+    }
+
+    // Markdown for embeds, local files have nothing to link to
+    pub fn link(&self) -> String {
+        match self.source {
+            TrackSource::File => format!("**{}**", self.display_title()),
+            _ => format!("[{}]({})", self.display_title(), self.url),
+        }
+    }
+
+    pub fn input(&self, ytdlp: &YtDlp) -> Input {
+        match self.source {
+            TrackSource::YoutubeDl => ytdlp.source(&self.url).into(),
+            TrackSource::Http => HttpRequest::new(ytdlp.http_client().clone(), self.url.clone()).into(),
+            TrackSource::File => File::new(self.url.clone()).into(),
+        }
+    }
+}
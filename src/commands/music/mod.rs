@@ -1,8 +1,25 @@
+pub mod attachment;
+pub mod autoplay;
 pub mod clear;
+pub mod crossfade;
 pub mod leave;
+pub mod library;
+pub mod links;
 pub mod current;
+pub mod filter;
+pub mod history;
+pub mod metadata;
 pub mod pause;
+pub mod pitch;
 pub mod play;
+pub mod playlist;
+pub mod previous;
+pub mod queue;
+pub mod radio;
+pub mod resolver;
 pub mod resume;
+pub mod search;
 pub mod skip;
+pub mod speed;
 pub mod stop;
+pub mod stream;
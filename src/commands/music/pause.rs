@@ -3,7 +3,9 @@ use serenity::framework::standard::CommandResult;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
-use crate::commands::utils::{get_guild_id_from_message, send_error_message, send_success_message};
+use crate::commands::utils::{
+    get_crossfades, get_guild_id_from_message, send_error_message, send_success_message,
+};
 
 #[command]
 #[only_in(guilds)]
@@ -26,6 +28,7 @@ async fn pause(ctx: &Context, msg: &Message) -> CommandResult {
             println!("Error pausing track: {}", e);
             send_error_message(&ctx, msg, "Error pausing track.").await?;
         } else {
+            get_crossfades(ctx).await.pause(guild_id);
             send_success_message(&ctx, msg, ":pause_button: Paused!").await?;
         }
     } else {
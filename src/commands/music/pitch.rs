@@ -0,0 +1,59 @@
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tracing::info;
+
+use crate::commands::utils::{get_filters, get_guild_id_from_message, get_prefix, send_success_message, send_warning};
+use crate::filters::MAX_PITCH;
+
+// Moves the pitch by semitones without changing the speed, from where the
+// current song is and for every song after it, until `pitch reset`
+#[command]
+#[only_in(guilds)]
+async fn pitch(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+    let filters = get_filters(ctx, guild_id).await;
+
+    let value = args.rest().trim().to_ascii_lowercase();
+    if value.is_empty() {
+        let prefix = get_prefix(ctx, msg).await;
+        let pitch = filters.get().pitch.unwrap_or(0.0);
+        send_success_message(
+            ctx,
+            msg,
+            &format!(
+                ":control_knobs: Pitch is {:+} semitones. Change it with `{}pitch <-{}..+{}>` or `{}pitch reset`.",
+                pitch, prefix, MAX_PITCH, MAX_PITCH, prefix
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let pitch = if matches!(value.as_str(), "reset" | "off" | "normal") {
+        0.0
+    } else {
+        match value.parse::<f64>() {
+            Ok(pitch) if (-MAX_PITCH..=MAX_PITCH).contains(&pitch) => pitch,
+            _ => {
+                send_warning(
+                    ctx,
+                    msg,
+                    &format!(
+                        "The pitch must be a number of semitones between -{} and +{}, e.g. `-2`.",
+                        MAX_PITCH, MAX_PITCH
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+        }
+    };
+
+    filters.update(|settings| settings.pitch = (pitch != 0.0).then_some(pitch));
+    info!("pitch: {} set the pitch in guild {:?} to {:+}", msg.author.name, guild_id, pitch);
+    send_success_message(ctx, msg, &format!(":control_knobs: Pitch set to {:+} semitones", pitch)).await?;
+
+    Ok(())
+}
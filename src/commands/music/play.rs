@@ -1,21 +1,45 @@
-
-use regex::Regex;
+use std::fmt;
+use std::future::Future;
 use std::sync::Arc;
 use serenity::framework::standard::macros::command;
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::model::prelude::*;
 use serenity::{prelude::*, async_trait};
 
-use songbird::input::YoutubeDl;
+use songbird::input::Input;
+use songbird::tracks::{PlayMode, Track, TrackHandle};
 use songbird::{Call, EventContext, Songbird, TrackEvent};
-use tokio::process::Command as TokioCommand;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use songbird::events::{Event, EventHandler as VoiceEventHandler};
-use tokio::time::{timeout, Duration};
+use tokio::time::{timeout, Duration, Instant};
 use tracing::{info, warn, debug};
 
-use crate::HttpKey;
-use crate::commands::utils::{send_error_message, send_success_message};
+use serenity::builder::{CreateEmbed, CreateMessage, EditMessage};
+use serenity::http::Http;
+
+use crate::{GuildSettingsKey, HttpKey, TrackHistoryKey, YtDlpKey};
+use crate::cache::TrackCache;
+use crate::config::Config;
+use crate::filters::{AudioFilters, PlaybackProgress};
+use crate::commands::music::autoplay::find_related_track;
+use crate::commands::music::crossfade::{Crossfader, CROSSFADE_CHECK_INTERVAL};
+use crate::commands::music::history::{HistoryRecorder, TrackHistory};
+use crate::commands::music::metadata::{StreamTitle, TrackMetadata, TrackSource};
+use crate::commands::music::resolver::{ResolveError, ResolverRegistry, TrackQuery};
+use crate::commands::music::search::with_default_provider;
+use crate::commands::music::stream::StreamTitleWatcher;
+use crate::commands::utils::{
+    get_config, get_crossfades, get_filters, get_guild_settings, get_resolvers, get_track_cache, get_ytdlp,
+    send_error_message, send_success_message, send_warning, to_time,
+};
+use crate::settings::{GuildSettings, GuildSettingsStore};
+use crate::ytdlp::{FlatEntry, YtDlp, YtDlpError, YtDlpSource};
 
+// Number of upcoming tracks kept ready to play while the current one runs
+const PRELOAD_AHEAD: usize = 2;
+
+// How often the progress message of a loading playlist is edited
+const PLAYLIST_PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
 
 #[command]
 #[aliases(p)]
@@ -23,13 +47,17 @@ use crate::commands::utils::{send_error_message, send_success_message};
 async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     debug!("play: Command invoked by {} with args: {:?}", msg.author.name, args.rest());
     
+    // An uploaded audio file is played instead of any text argument
+    let attachment = msg.attachments.first();
+
     let url = match get_url_from_args(&args) {
         Some(url) => url,
+        None if attachment.is_some() => String::new(),
         None => {
             send_error_message(
                 &ctx,
                 msg,
-                "Use the command like this: play <url> or <song name>",
+                "Use the command like this: play <url> or <song name>, or attach an audio file",
             )
             .await?;
             return Ok(());
@@ -89,21 +117,64 @@ async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
         }
     };
 
-    let mut handler = handler_lock.lock().await;
-    debug!("play: Handler locked successfully");
+    let config = get_config(ctx).await;
+    let settings = get_guild_settings(ctx, guild_id).await;
 
-    if !url.starts_with("http") {
-        info!("play: Searching for track: {}", url);
-        search_and_play_single_track(&ctx, msg, &mut handler, &url).await?;
-    } else if url.contains("index") {
+    // Playlists lock the call for each track they add rather than throughout
+    let is_playlist = url.starts_with("http") && url.contains("index");
+    if attachment.is_none() && is_playlist {
         info!("play: Playing playlist: {}", url);
-        play_playlist(&ctx, msg, &mut handler, &url).await?;
-    } else if url.contains("live") {
-        info!("play: Playing live stream: {}", url);
-        play_live_stream(&ctx, msg, &mut handler, &url).await?;
-    } else {
-        info!("play: Playing direct link: {}", url);
-        play_direct_link(&ctx, msg, &mut handler, &url).await?;
+        return play_playlist(ctx, msg, manager, guild_id, &settings, &url).await;
+    }
+
+    // The call is only locked to look at or append to the queue; resolving
+    // the track happens without it so `pause`, `skip` etc. stay responsive
+    let queue_length = handler_lock.lock().await.queue().len();
+    let max_queue_length = settings.max_queue_length(config);
+    if max_queue_length > 0 && queue_length >= max_queue_length {
+        send_error_message(
+            ctx,
+            msg,
+            &format!("The queue is full ({} tracks).", max_queue_length),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let text = with_default_provider(&url, settings.search_provider());
+    let query = match attachment {
+        Some(attachment) => TrackQuery::Attachment(attachment),
+        None => TrackQuery::Text(&text),
+    };
+    let resolvers = get_resolvers(ctx).await;
+    let filters = get_filters(ctx, guild_id).await;
+    match enqueue_query(&handler_lock, &resolvers, &query, &msg.author, &settings, config, &filters).await {
+        Ok(enqueued) => {
+            info!(
+                "play: Enqueued {} tracks for {}, skipped {}",
+                enqueued.tracks.len(),
+                msg.author.name,
+                enqueued.skipped()
+            );
+            let title = match (&enqueued.tracks[..], enqueued.skipped()) {
+                ([track], 0) => format!(":notes: Added to queue: **{}**", track.title),
+                (tracks, 0) => format!(":notes: Added {} tracks to the queue", tracks.len()),
+                (tracks, skipped) => format!(
+                    ":warning: Added {} tracks to the queue. {} skipped ({} over the length limit, the rest due to queue limits).",
+                    tracks.len(),
+                    skipped,
+                    enqueued.too_long
+                ),
+            };
+            send_success_message(ctx, msg, &title).await?;
+        }
+        Err(EnqueueError::Resolve(ResolveError::Rejected(reason))) => {
+            send_warning(ctx, msg, &reason).await?;
+        }
+        Err(e) => {
+            warn!("play: Failed to enqueue '{}': {:?}", url, e);
+            send_error_message(ctx, msg, &e.to_string()).await?;
+        }
     }
 
     Ok(())
@@ -130,7 +201,7 @@ async fn get_guild_id(msg: &Message, ctx: &Context) -> Result<GuildId, &'static
 }
 
 
-async fn join_channel_if_needed(ctx: &Context, msg: &Message) -> Result<(), String> {
+pub async fn join_channel_if_needed(ctx: &Context, msg: &Message) -> Result<(), String> {
     debug!("join_channel_if_needed: Started for user {}", msg.author.name);
     
     let (guild_id, channel_id) = {
@@ -145,11 +216,6 @@ async fn join_channel_if_needed(ctx: &Context, msg: &Message) -> Result<(), Stri
 
     debug!("join_channel_if_needed: Guild ID: {:?}, Channel ID: {:?}", guild_id, channel_id);
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
     let connect_to = match channel_id {
         Some(channel) => {
             debug!("join_channel_if_needed: User is in channel {:?}", channel);
@@ -161,62 +227,153 @@ async fn join_channel_if_needed(ctx: &Context, msg: &Message) -> Result<(), Stri
         }
     };
 
+    join_voice_channel(ctx, guild_id, connect_to).await
+}
+
+pub async fn join_voice_channel(
+    ctx: &Context,
+    guild_id: GuildId,
+    connect_to: ChannelId,
+) -> Result<(), String> {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    let config = get_config(ctx).await;
+    let (http_client, ytdlp, history, settings) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<HttpKey>().cloned().expect("Should exist in typemap"),
+            data.get::<YtDlpKey>().cloned().expect("Should exist in typemap"),
+            data.get::<TrackHistoryKey>().cloned().expect("Should exist in typemap"),
+            data.get::<GuildSettingsKey>().cloned().expect("Should exist in typemap"),
+        )
+    };
+    // Filters only live in memory, so they take the saved normalization
+    // setting from here, before anything is queued
+    let filters = get_filters(ctx, guild_id).await;
+    filters.set_normalize(settings.get(guild_id).await.normalize);
+    let crossfades = get_crossfades(ctx).await;
+
     // Retry logic with exponential backoff
     // Discord voice gateway can have transient issues that resolve quickly
     for attempt in 1..=3 {
-        debug!("join_channel_if_needed: Attempt {} of 3 to join guild {:?} channel {:?}", 
+        debug!("join_voice_channel: Attempt {} of 3 to join guild {:?} channel {:?}", 
                attempt, guild_id, connect_to);
         
         match timeout(Duration::from_secs(20), manager.join(guild_id, connect_to)).await {
             Ok(Ok(handler_lock)) => {
-                info!("join_channel_if_needed: Successfully joined voice channel on attempt {}", attempt);
+                info!("join_voice_channel: Successfully joined voice channel on attempt {}", attempt);
                 let mut handler = handler_lock.lock().await;
                 handler.add_global_event(TrackEvent::Error.into(), TrackErrorNotifier);
+                handler.add_global_event(
+                    TrackEvent::Play.into(),
+                    NowPlayingNotifier {
+                        http: ctx.http.clone(),
+                        settings: settings.clone(),
+                        guild_id,
+                    },
+                );
+                handler.add_global_event(
+                    TrackEvent::Play.into(),
+                    TrackPreloader {
+                        manager: manager.clone(),
+                        guild_id,
+                    },
+                );
+                handler.add_global_event(
+                    TrackEvent::Play.into(),
+                    StreamTitleWatcher {
+                        http_client: http_client.clone(),
+                    },
+                );
+                handler.add_global_event(
+                    TrackEvent::End.into(),
+                    HistoryRecorder {
+                        history: history.clone(),
+                        guild_id,
+                    },
+                );
                 handler.add_global_event(
                     TrackEvent::End.into(),
                     QueueEndNotifier {
                         manager: manager.clone(),
                         guild_id,
+                        config,
+                        ytdlp: ytdlp.clone(),
+                        history: history.clone(),
+                        settings: settings.clone(),
+                        filters: filters.clone(),
                     },
                 );
-                debug!("join_channel_if_needed: Added error notifier");
+                handler.add_global_event(
+                    Event::Periodic(CROSSFADE_CHECK_INTERVAL, None),
+                    Crossfader {
+                        manager: manager.clone(),
+                        guild_id,
+                        settings: settings.clone(),
+                        crossfades: crossfades.clone(),
+                    },
+                );
+                debug!("join_voice_channel: Added error notifier and history recorder");
                 return Ok(());
             }
             Ok(Err(err)) => {
-                warn!("join_channel_if_needed: Attempt {} failed with error: {}", attempt, err);
+                warn!("join_voice_channel: Attempt {} failed with error: {}", attempt, err);
                 if attempt == 3 {
                     let err_msg = format!("Failed to join voice channel after 3 attempts. Discord may be experiencing issues. Error: {}", err);
-                    warn!("join_channel_if_needed: {}", err_msg);
+                    warn!("join_voice_channel: {}", err_msg);
                     return Err(err_msg);
                 }
                 let wait_ms = 2000 * attempt as u64;
-                debug!("join_channel_if_needed: Waiting {}ms before retry", wait_ms);
+                debug!("join_voice_channel: Waiting {}ms before retry", wait_ms);
                 tokio::time::sleep(Duration::from_millis(wait_ms)).await;
             }
             Err(_) => {
-                warn!("join_channel_if_needed: Attempt {} timed out after 20 seconds", attempt);
+                warn!("join_voice_channel: Attempt {} timed out after 20 seconds", attempt);
                 if attempt == 3 {
                     let err_msg = "Joining voice channel timed out after 3 attempts (Discord gateway unresponsive). Please try again. If this persists, check Discord's status page.";
-                    warn!("join_channel_if_needed: {}", err_msg);
+                    warn!("join_voice_channel: {}", err_msg);
                     return Err(err_msg.to_string());
                 }
                 let wait_ms = 2000 * attempt as u64;
-                debug!("join_channel_if_needed: Waiting {}ms before retry after timeout", wait_ms);
+                debug!("join_voice_channel: Waiting {}ms before retry after timeout", wait_ms);
                 tokio::time::sleep(Duration::from_millis(wait_ms)).await;
             }
         }
     }
 
     let err_msg = "Failed to join voice channel after 3 attempts".to_string();
-    warn!("join_channel_if_needed: {}", err_msg);
+    warn!("join_voice_channel: {}", err_msg);
     Err(err_msg)
 }
 
 struct TrackErrorNotifier;
 
+// Posts each track as it starts to the guild's announce channel, if one is set
+struct NowPlayingNotifier {
+    http: Arc<Http>,
+    settings: GuildSettingsStore,
+    guild_id: GuildId,
+}
+
+// Readies the next tracks whenever one starts, so they don't wait for yt-dlp
+// and their stream to open once it's their turn
+struct TrackPreloader {
+    manager: Arc<Songbird>,
+    guild_id: GuildId,
+}
+
+#[derive(Clone)]
 struct QueueEndNotifier {
     manager: Arc<Songbird>,
     guild_id: GuildId,
+    config: &'static Config,
+    ytdlp: YtDlp,
+    history: TrackHistory,
+    settings: GuildSettingsStore,
+    filters: AudioFilters,
 }
 
 #[async_trait]
@@ -237,23 +394,38 @@ impl VoiceEventHandler for TrackErrorNotifier {
 }
 
 #[async_trait]
-impl VoiceEventHandler for QueueEndNotifier {
-    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
-        if let Some(handler_lock) = self.manager.get(self.guild_id) {
-            let should_leave = {
-                let handler = handler_lock.lock().await;
-                handler.queue().current().is_none()
-            };
+impl VoiceEventHandler for NowPlayingNotifier {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(track_list) = ctx else {
+            return None;
+        };
 
-            if should_leave {
-                match self.manager.remove(self.guild_id).await {
-                    Ok(_) => info!("Queue empty in guild {:?}, left voice channel", self.guild_id),
-                    Err(err) => warn!(
-                        "Failed to leave voice channel in guild {:?} after queue finished: {}",
-                        self.guild_id,
-                        err
-                    ),
-                }
+        // `Play` also fires when a paused track resumes; only announce starts
+        let started: Vec<_> = track_list
+            .iter()
+            .filter(|(state, _)| state.play_time < Duration::from_secs(1))
+            .map(|(_, handle)| handle.data::<TrackMetadata>())
+            .collect();
+        if started.is_empty() {
+            return None;
+        }
+
+        let channel_id = self.settings.get(self.guild_id).await.announce_channel?;
+
+        for metadata in started {
+            let embed = CreateEmbed::default()
+                .color(0xffffff)
+                .title(format!(":notes: Now playing: **{}**", metadata.display_title()))
+                .url(metadata.url.clone())
+                .description(format!("Requested by {}", metadata.requester_name))
+                .timestamp(Timestamp::now());
+            let builder = CreateMessage::default().add_embed(embed);
+
+            if let Err(e) = channel_id.send_message(&self.http, builder).await {
+                warn!(
+                    "NowPlayingNotifier: Failed to announce in guild {:?}: {}",
+                    self.guild_id, e
+                );
             }
         }
 
@@ -261,174 +433,682 @@ impl VoiceEventHandler for QueueEndNotifier {
     }
 }
 
-async fn search_and_play_single_track(
-    ctx: &Context,
-    msg: &Message,
-    handler: &mut Call,
-    query: &str,
-) -> CommandResult {
-    debug!("search_and_play_single_track: Searching for '{}'", query);
-    
-    let http_client = {
-        let data = ctx.data.read().await;
-        data.get::<HttpKey>()
-        .cloned()
-        .expect("Should exist in typemap")
-    };
+#[async_trait]
+impl VoiceEventHandler for TrackPreloader {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(track_list) = ctx else {
+            return None;
+        };
 
-    let source = YoutubeDl::new_search(http_client, query.to_string());
-    match handler.enqueue(source.into()).await {
-        _ => {
-            info!("search_and_play_single_track: Enqueued search result for '{}'", query);
+        // `Play` also fires when a paused track resumes
+        if !track_list
+            .iter()
+            .any(|(state, _)| state.play_time < Duration::from_secs(1))
+        {
+            return None;
         }
+
+        // The call is locked by whoever changes the queue, so stay off the
+        // event thread
+        let manager = self.manager.clone();
+        let guild_id = self.guild_id;
+        tokio::spawn(async move {
+            let Some(handler_lock) = manager.get(guild_id) else {
+                return;
+            };
+
+            let upcoming = handler_lock.lock().await.queue().current_queue();
+            for handle in upcoming.iter().skip(1).take(PRELOAD_AHEAD) {
+                // Already prepared tracks ignore this
+                drop(handle.make_playable());
+            }
+        });
+
+        None
     }
+}
 
-    let _ = send_success_message(ctx, msg, &format!(":mag: Searching and queuing: **{}**", query)).await;
+#[async_trait]
+impl VoiceEventHandler for QueueEndNotifier {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        // Only a track that played to its end hands over to autoplay; `stop`
+        // and `skip` end tracks manually.
+        let finished = match ctx {
+            EventContext::Track(track_list) => track_list
+                .iter()
+                .find(|(state, _)| matches!(state.playing, PlayMode::End))
+                .map(|(_, handle)| handle.data::<TrackMetadata>()),
+            _ => None,
+        };
 
-    Ok(())
+        // Resolving a related track runs yt-dlp, so keep it off the event thread
+        let notifier = self.clone();
+        tokio::spawn(async move {
+            notifier.on_track_end(finished).await;
+        });
+
+        None
+    }
+}
+
+impl QueueEndNotifier {
+    async fn on_track_end(&self, finished: Option<Arc<TrackMetadata>>) {
+        let handler_lock = match self.manager.get(self.guild_id) {
+            Some(handler_lock) => handler_lock,
+            None => return,
+        };
+
+        let queue_empty = {
+            let handler = handler_lock.lock().await;
+            handler.queue().current().is_none()
+        };
+
+        if !queue_empty {
+            return;
+        }
+
+        let settings = self.settings.get(self.guild_id).await;
+
+        if let Some(last) = finished {
+            if settings.autoplay {
+                let related = find_related_track(
+                    &self.ytdlp,
+                    &self.history,
+                    self.guild_id,
+                    &last,
+                )
+                .await;
+
+                match related {
+                    Some((source, metadata)) => {
+                        let mut handler = handler_lock.lock().await;
+                        // Someone may have queued a track while we were searching
+                        if handler.queue().current().is_none() {
+                            info!(
+                                "Autoplay in guild {:?}: queuing '{}'",
+                                self.guild_id, metadata.title
+                            );
+                            enqueue_with_metadata(
+                                &mut handler,
+                                source,
+                                metadata,
+                                settings.default_volume(self.config),
+                                &self.filters,
+                            );
+                        }
+                        return;
+                    }
+                    None => warn!(
+                        "Autoplay in guild {:?} found nothing related to '{}'",
+                        self.guild_id, last.title
+                    ),
+                }
+            }
+        }
+
+        let idle_timeout = settings.idle_timeout(self.config);
+        if !idle_timeout.is_zero() {
+            debug!(
+                "Queue empty in guild {:?}, leaving in {}s unless something is queued",
+                self.guild_id,
+                idle_timeout.as_secs()
+            );
+            tokio::time::sleep(idle_timeout).await;
+
+            let still_idle = match self.manager.get(self.guild_id) {
+                Some(handler_lock) => handler_lock.lock().await.queue().current().is_none(),
+                None => false,
+            };
+            if !still_idle {
+                return;
+            }
+        }
+
+        match self.manager.remove(self.guild_id).await {
+            Ok(_) => info!("Queue empty in guild {:?}, left voice channel", self.guild_id),
+            Err(err) => warn!(
+                "Failed to leave voice channel in guild {:?} after queue finished: {}",
+                self.guild_id,
+                err
+            ),
+        }
+    }
 }
 
+// Entries are queued as yt-dlp lists them, locking the call only to append
+// each one, while a progress message keeps count. Returns once the progress
+// message is posted; the rest happens in the background.
 async fn play_playlist(
     ctx: &Context,
     msg: &Message,
-    handler: &mut Call,
+    manager: Arc<Songbird>,
+    guild_id: GuildId,
+    settings: &GuildSettings,
     playlist_url: &str,
 ) -> CommandResult {
     info!("play_playlist: Processing playlist: {}", playlist_url);
-    debug!("play_playlist: Running yt-dlp command");
-    
-    let raw_playlist_output = TokioCommand::new("yt-dlp")
-        .args(["-j", "--flat-playlist", playlist_url])
-        .output()
-        .await;
-
-    let raw_playlist = match raw_playlist_output {
-        Ok(output) => match String::from_utf8(output.stdout) {
-            Ok(s) => {
-                debug!("play_playlist: yt-dlp output received, length: {} bytes", s.len());
-                s
+
+    let progress = msg
+        .channel_id
+        .send_message(
+            &ctx.http,
+            CreateMessage::default().add_embed(playlist_progress_embed(
+                ":hourglass: Loading playlist...",
+                "Waiting for the first track",
+            )),
+        )
+        .await?;
+
+    let loader = PlaylistLoader {
+        ctx: ctx.clone(),
+        requester: msg.author.clone(),
+        manager,
+        guild_id,
+        settings: settings.clone(),
+        config: get_config(ctx).await,
+        cache: get_track_cache(ctx).await,
+        ytdlp: get_ytdlp(ctx).await,
+        filters: get_filters(ctx, guild_id).await,
+        progress,
+    };
+    let url = playlist_url.to_string();
+    tokio::spawn(async move {
+        loader.run(&url).await;
+    });
+
+    Ok(())
+}
+
+fn playlist_progress_embed(title: &str, description: &str) -> CreateEmbed {
+    CreateEmbed::default()
+        .color(0xffffff)
+        .title(title)
+        .description(description)
+        .timestamp(Timestamp::now())
+}
+
+struct PlaylistLoader {
+    ctx: Context,
+    requester: User,
+    manager: Arc<Songbird>,
+    guild_id: GuildId,
+    settings: GuildSettings,
+    config: &'static Config,
+    cache: TrackCache,
+    ytdlp: YtDlp,
+    filters: AudioFilters,
+    progress: Message,
+}
+
+#[derive(Default)]
+struct PlaylistProgress {
+    added: usize,
+    too_long: usize,
+    failed: usize,
+    // The queue or playlist limit was reached, the rest wasn't read
+    limited: bool,
+    // yt-dlp went quiet before listing every entry
+    timed_out: bool,
+}
+
+impl PlaylistLoader {
+    async fn run(mut self, playlist_url: &str) {
+        let mut process = match self.ytdlp.spawn(&["-j", "--flat-playlist", playlist_url]).await {
+            Ok(process) => process,
+            Err(e) => {
+                warn!("play_playlist: yt-dlp command failed: {:?}", e);
+                self.update(":x: Failed to retrieve playlist", &e.to_string()).await;
+                return;
             }
-            Err(_) => {
-                warn!("play_playlist: Failed to parse yt-dlp output as UTF-8");
-                send_error_message(&ctx, msg, "Failed to parse playlist data")
-                    .await?;
-                return Ok(());
+        };
+        let stdout = process.child.stdout.take().expect("stdout is piped");
+        let mut lines = BufReader::new(stdout).lines();
+
+        let playlist_space = match self.settings.max_playlist_length(self.config) {
+            0 => usize::MAX,
+            max => max,
+        };
+        let mut progress = PlaylistProgress::default();
+        let mut last_update = Instant::now();
+
+        loop {
+            // The timeout applies per entry, a long playlist may take a while
+            let line = match timeout(self.ytdlp.timeout(), lines.next_line()).await {
+                Ok(Ok(Some(line))) => line,
+                Ok(Ok(None)) => break,
+                Ok(Err(e)) => {
+                    warn!("play_playlist: Failed to read yt-dlp output: {}", e);
+                    break;
+                }
+                Err(_) => {
+                    warn!("play_playlist: yt-dlp stopped responding on {}", playlist_url);
+                    progress.timed_out = true;
+                    break;
+                }
+            };
+
+            let entry = match serde_json::from_str::<FlatEntry>(&line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    debug!("play_playlist: Skipping unreadable entry: {}", e);
+                    progress.failed += 1;
+                    continue;
+                }
+            };
+            let Some(url) = entry.url.filter(|url| url.starts_with("http")) else {
+                progress.failed += 1;
+                continue;
+            };
+
+            // Stopping here also ends yt-dlp, rather than reading the rest
+            if progress.added >= playlist_space {
+                progress.limited = true;
+                break;
+            }
+
+            let metadata = TrackMetadata {
+                title: entry.title.unwrap_or_else(|| url.clone()),
+                url,
+                duration: entry.duration.map(Duration::from_secs_f64),
+                requester_id: self.requester.id,
+                requester_name: self.requester.name.clone(),
+                autoplay: false,
+                source: TrackSource::YoutubeDl,
+                stream_title: StreamTitle::default(),
+                progress: PlaybackProgress::default(),
+            };
+
+            // Leaving the channel ends the import, yt-dlp is killed on drop
+            let Some(handler_lock) = self.manager.get(self.guild_id) else {
+                info!("play_playlist: Left the voice channel, stopping");
+                break;
+            };
+
+            {
+                let mut handler = handler_lock.lock().await;
+                let max_queue_length = self.settings.max_queue_length(self.config);
+                if max_queue_length > 0 && handler.queue().len() >= max_queue_length {
+                    progress.limited = true;
+                    break;
+                }
+
+                match enqueue_known(
+                    &mut handler,
+                    &self.ytdlp,
+                    metadata,
+                    &self.settings,
+                    self.config,
+                    &self.cache,
+                    &self.filters,
+                ) {
+                    Ok(_) => progress.added += 1,
+                    Err(EnqueueError::TooLong { .. }) => progress.too_long += 1,
+                    Err(e) => {
+                        warn!("play_playlist: Failed to enqueue track: {:?}", e);
+                        progress.failed += 1;
+                    }
+                }
+            }
+
+            if progress.added == 1 || last_update.elapsed() >= PLAYLIST_PROGRESS_INTERVAL {
+                last_update = Instant::now();
+                self.update(
+                    ":hourglass: Loading playlist...",
+                    &format!("{} tracks added so far", progress.added),
+                )
+                .await;
             }
-        },
-        Err(e) => {
-            warn!("play_playlist: yt-dlp command failed: {}", e);
-            send_error_message(&ctx, msg, "Failed to retrieve playlist").await?;
-            return Ok(());
         }
-    };
 
-    let playlist_regex =
-        Regex::new(r#""url": "(https://www.youtube.com/watch\?v=[A-Za-z0-9]{11})""#).unwrap();
-    let track_urls: Vec<String> = playlist_regex
-        .captures_iter(&raw_playlist)
-        .map(|cap| cap[1].to_string())
-        .collect();
+        // Stops yt-dlp if we quit early, otherwise just reaps it
+        let _ = process.child.kill().await;
+        // Hands the worker back before the summary is posted
+        drop(process);
 
-    if track_urls.is_empty() {
-        warn!("play_playlist: No tracks found in playlist: {}", playlist_url);
-        send_error_message(&ctx, msg, "No tracks found in the playlist").await?;
-        return Ok(());
-    }
+        info!(
+            "play_playlist: Playlist queued - {} tracks, {} too long, {} errors, limited: {}",
+            progress.added, progress.too_long, progress.failed, progress.limited
+        );
 
-    info!("play_playlist: Found {} tracks in playlist", track_urls.len());
+        if progress.added == 0 && progress.too_long == 0 && !progress.limited {
+            warn!("play_playlist: No tracks found in playlist: {}", playlist_url);
+            let reason = if progress.timed_out {
+                "Looking up the playlist took too long."
+            } else {
+                ""
+            };
+            self.update(":x: No tracks found in the playlist", reason).await;
+            return;
+        }
 
-    let http_client = {
-        let data = ctx.data.read().await;
-        data.get::<HttpKey>()
-        .cloned()
-        .expect("Should exist in typemap")
-    };
+        let mut notes = Vec::new();
+        if progress.timed_out {
+            notes.push("Looking up the playlist took too long, the rest was left out.".to_string());
+        }
+        if progress.too_long > 0 {
+            notes.push(format!("{} tracks over the length limit were skipped.", progress.too_long));
+        }
+        if progress.limited {
+            notes.push("The rest of the playlist was skipped due to queue limits.".to_string());
+        }
+        if progress.failed > 0 {
+            notes.push(format!("{} entries could not be read.", progress.failed));
+        }
 
-    let mut track_errors = 0;
+        let title = if notes.is_empty() {
+            format!(":notes: Playlist queued successfully! {} tracks added.", progress.added)
+        } else {
+            format!(":warning: Playlist queued, {} tracks added.", progress.added)
+        };
+        self.update(&title, &notes.join("\n")).await;
+    }
 
-    for (idx, track_url) in track_urls.iter().cloned().enumerate() {
-        let track = YoutubeDl::new(http_client.clone(), track_url.clone());
-        match handler.enqueue(track.into()).await {
-            _ => {
-                debug!("play_playlist: Enqueued track {}/{}", idx + 1, track_urls.len());
-            }
+    async fn update(&mut self, title: &str, description: &str) {
+        let builder = EditMessage::new().embed(playlist_progress_embed(title, description));
+        if let Err(e) = self.progress.edit(&self.ctx.http, builder).await {
+            debug!("play_playlist: Failed to update progress message: {}", e);
         }
     }
+}
 
-    let queued_message = if track_errors == 0 {
-        format!(
-            ":notes: Playlist queued successfully! {} tracks added.",
-            track_urls.len()
-        )
-    } else {
-        format!(
-            ":warning: Playlist queued with {} errors. {} tracks added.",
-            track_errors,
-            track_urls.len() - track_errors
-        )
+#[derive(Debug)]
+pub enum EnqueueError {
+    Resolve(ResolveError),
+    TooLong { duration: Duration, limit: Duration },
+}
+
+impl fmt::Display for EnqueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnqueueError::Resolve(e) => write!(f, "{}", e),
+            EnqueueError::TooLong { duration, limit } => write!(
+                f,
+                "The track is {} long, tracks may be at most {}.",
+                to_time(duration.as_secs()),
+                to_time(limit.as_secs())
+            ),
+        }
+    }
+}
+
+// What `enqueue_query` added, and how many tracks it left out
+#[derive(Debug, Default)]
+pub struct Enqueued {
+    pub tracks: Vec<TrackMetadata>,
+    // Over the guild's track length limit
+    pub too_long: usize,
+    // Beyond the guild's queue length limit
+    pub queue_full: usize,
+}
+
+impl Enqueued {
+    pub fn skipped(&self) -> usize {
+        self.too_long + self.queue_full
+    }
+}
+
+// Resolves the query and appends what it turned into to the queue. The call
+// is only locked for the append. Of an album or playlist, tracks that are too
+// long or don't fit in the queue are left out; a single track that is too
+// long is an error.
+pub async fn enqueue_query(
+    handler_lock: &Mutex<Call>,
+    resolvers: &ResolverRegistry,
+    query: &TrackQuery<'_>,
+    requester: &User,
+    settings: &GuildSettings,
+    config: &Config,
+    filters: &AudioFilters,
+) -> Result<Enqueued, EnqueueError> {
+    let resolve = async {
+        let mut tracks = resolvers
+            .resolve(query, requester, settings.max_playlist_length(config))
+            .await
+            .map_err(EnqueueError::Resolve)?;
+        if let [track] = &tracks[..] {
+            check_duration(&track.metadata, settings, config)?;
+        }
+
+        let resolved = tracks.len();
+        tracks.retain(|track| check_duration(&track.metadata, settings, config).is_ok());
+        let too_long = resolved - tracks.len();
+
+        Ok((tracks, too_long))
     };
 
-    info!("play_playlist: Playlist queued - {} tracks, {} errors", track_urls.len(), track_errors);
-    let _ = send_success_message(ctx, msg, &queued_message).await;
+    let volume = settings.default_volume(config);
+    let max_queue_length = settings.max_queue_length(config);
+    lock_after(handler_lock, resolve, |handler, resolved| {
+        let (tracks, too_long) = resolved?;
 
-    Ok(())
+        // The queue may have filled up while the query was being resolved
+        let queue_space = match max_queue_length {
+            0 => usize::MAX,
+            max => max.saturating_sub(handler.queue().len()),
+        };
+        let queue_full = tracks.len().saturating_sub(queue_space);
+
+        let tracks = tracks
+            .into_iter()
+            .take(queue_space)
+            .map(|track| {
+                let metadata = track.metadata.clone();
+                enqueue_with_metadata(handler, track.input, track.metadata, volume, filters);
+                metadata
+            })
+            .collect();
+
+        Ok(Enqueued {
+            tracks,
+            too_long,
+            queue_full,
+        })
+    })
+    .await
 }
 
-async fn play_live_stream(
-    ctx: &Context,
-    msg: &Message,
+// Queues a track whose metadata is already known, e.g. from a saved playlist,
+// under the same duration limit `enqueue_query` applies but without asking
+// yt-dlp again.
+pub fn enqueue_known(
     handler: &mut Call,
-    stream_url: &str,
-) -> CommandResult {
-    debug!("play_live_stream: Processing stream: {}", stream_url);
-    
-    let url = stream_url.to_string();
+    ytdlp: &YtDlp,
+    metadata: TrackMetadata,
+    settings: &GuildSettings,
+    config: &'static Config,
+    cache: &TrackCache,
+    filters: &AudioFilters,
+) -> Result<TrackHandle, EnqueueError> {
+    check_duration(&metadata, settings, config)?;
+    let source = cache.input(&metadata, ytdlp);
 
-    let http_client = {
-        let data = ctx.data.read().await;
-        data.get::<HttpKey>()
-        .cloned()
-        .expect("Should exist in typemap")
-    };
+    Ok(enqueue_with_metadata(handler, source, metadata, settings.default_volume(config), filters))
+}
 
-    let source = YoutubeDl::new(http_client, url);
-    match handler.enqueue(source.into()).await {
-        _ => {
-            info!("play_live_stream: Enqueued live stream");
+pub fn check_duration(
+    metadata: &TrackMetadata,
+    settings: &GuildSettings,
+    config: &Config,
+) -> Result<(), EnqueueError> {
+    if let (Some(duration), Some(limit)) = (metadata.duration, settings.max_track_duration(config)) {
+        if duration > limit {
+            return Err(EnqueueError::TooLong { duration, limit });
         }
     }
 
-    let _ = send_success_message(ctx, msg, ":notes: Live stream added to queue!").await;
-
     Ok(())
 }
 
-async fn play_direct_link(
-    ctx: &Context,
-    msg: &Message,
+pub async fn resolve_metadata(
+    ytdlp: &YtDlp,
+    query: &str,
+    requester_id: UserId,
+    requester_name: &str,
+) -> Result<TrackMetadata, YtDlpError> {
+    resolve_track(ytdlp, query, requester_id, requester_name)
+        .await
+        .map(|(metadata, _)| metadata)
+}
+
+// Like `resolve_metadata`, with the source to play the track from. The
+// stream yt-dlp found is kept, so playing it right away needs no second run.
+pub async fn resolve_track(
+    ytdlp: &YtDlp,
+    query: &str,
+    requester_id: UserId,
+    requester_name: &str,
+) -> Result<(TrackMetadata, YtDlpSource), YtDlpError> {
+    let info = ytdlp.resolve(query).await?;
+    let metadata = TrackMetadata::from_info(&info, query, requester_id, requester_name);
+    let source = ytdlp.resolved_source(&metadata.url, &info);
+
+    Ok((metadata, source))
+}
+
+// The metadata is already known, so the preload time is derived from it
+// instead of letting songbird query the source again. Tracks landing right
+// behind the current one are readied straight away, the rest by
+// `TrackPreloader` as the queue moves up. Every track passes through the
+// guild's audio filters.
+pub fn enqueue_with_metadata(
     handler: &mut Call,
-    stream_url: &str,
-) -> CommandResult {
-    debug!("play_direct_link: Processing direct link: {}", stream_url);
-    
-    let url = stream_url.to_string();
+    source: impl Into<Input>,
+    metadata: TrackMetadata,
+    volume: f32,
+    filters: &AudioFilters,
+) -> TrackHandle {
+    let preload_time = metadata
+        .duration
+        .map(|duration| duration.saturating_sub(Duration::from_secs(5)));
+    let input = filters.wrap(source.into(), &metadata.progress);
+    let track = Track::new_with_data(input, Arc::new(metadata)).volume(volume);
 
-    let http_client = {
-        let data = ctx.data.read().await;
-        data.get::<HttpKey>()
-        .cloned()
-        .expect("Should exist in typemap")
+    let handle = handler.enqueue_with_preload(track, preload_time);
+    let position = handler.queue().len() - 1;
+    if (1..=PRELOAD_AHEAD).contains(&position) {
+        drop(handle.make_playable());
+    }
+
+    handle
+}
+
+// Awaits `resolve` before taking the lock and hands its output to `apply`.
+// Resolving can take seconds (yt-dlp, the network), and commands like
+// `pause` and `skip` wait for the call lock, so it must not be held meanwhile.
+async fn lock_after<C, T, R>(
+    lock: &Mutex<C>,
+    resolve: impl Future<Output = T>,
+    apply: impl FnOnce(&mut C, T) -> R,
+) -> R {
+    let resolved = resolve.await;
+    let mut guard = lock.lock().await;
+
+    apply(&mut guard, resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serenity::async_trait;
+    use serenity::model::id::{GuildId, UserId};
+    use serenity::model::user::User;
+    use songbird::Call;
+    use tokio::sync::Mutex;
+    use tokio::time::{sleep, timeout, Duration};
+
+    use super::{enqueue_query, lock_after};
+    use crate::commands::music::resolver::{
+        fake_track, FakeResolver, ResolveError, ResolvedTrack, ResolverRegistry, TrackQuery, TrackResolver,
     };
+    use crate::config::Config;
+    use crate::filters::AudioFilters;
+    use crate::settings::GuildSettings;
+
+    // Takes its time like yt-dlp would, then answers like `FakeResolver`
+    struct SlowResolver {
+        inner: FakeResolver,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl TrackResolver for SlowResolver {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
 
-    let source = YoutubeDl::new(http_client, url);
-    match handler.enqueue(source.into()).await {
-        _ => {
-            info!("play_direct_link: Enqueued track from direct link");
+        async fn resolve(
+            &self,
+            query: &TrackQuery<'_>,
+            requester: &User,
+            max_tracks: usize,
+        ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>> {
+            sleep(self.delay).await;
+            self.inner.resolve(query, requester, max_tracks).await
         }
     }
 
-    let _ = send_success_message(ctx, msg, ":notes: Track added to queue!").await;
+    // A slow yt-dlp lookup must not keep `pause` or `skip` waiting on the
+    // call lock
+    #[tokio::test]
+    async fn lock_is_free_while_resolving() {
+        let queue = Arc::new(Mutex::new(Vec::new()));
 
-    Ok(())
-}
+        let enqueue = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                let resolve = async {
+                    sleep(Duration::from_millis(200)).await;
+                    "track"
+                };
+                lock_after(&queue, resolve, |queue, track| queue.push(track)).await;
+            }
+        });
 
+        sleep(Duration::from_millis(20)).await;
+        let control = timeout(Duration::from_millis(50), queue.lock()).await;
+        assert!(control.is_ok(), "call lock held during resolution");
+        assert!(control.unwrap().is_empty());
+
+        enqueue.await.unwrap();
+        assert_eq!(*queue.lock().await, vec!["track"]);
+    }
+
+    // The same, through what `play` runs: the queue can be read while the
+    // resolver is still busy, and the track lands once it answers
+    #[tokio::test]
+    async fn enqueue_query_leaves_the_call_unlocked_while_resolving() {
+        let call = Arc::new(Mutex::new(Call::standalone(GuildId::new(1), UserId::new(2))));
+        let resolvers = ResolverRegistry::default().with(SlowResolver {
+            inner: FakeResolver {
+                tracks: vec![("song".to_string(), fake_track("song"))],
+            },
+            delay: Duration::from_millis(200),
+        });
+
+        let enqueue = tokio::spawn({
+            let call = call.clone();
+            async move {
+                enqueue_query(
+                    &call,
+                    &resolvers,
+                    &TrackQuery::Text("song"),
+                    &User::default(),
+                    &GuildSettings::default(),
+                    &Config::default(),
+                    &AudioFilters::default(),
+                )
+                .await
+            }
+        });
+
+        sleep(Duration::from_millis(20)).await;
+        let control = timeout(Duration::from_millis(50), call.lock()).await;
+        assert!(control.is_ok(), "call lock held during resolution");
+        assert!(control.unwrap().queue().is_empty());
+
+        let enqueued = enqueue.await.unwrap().unwrap();
+        assert_eq!(enqueued.tracks.len(), 1);
+        assert_eq!(call.lock().await.queue().len(), 1);
+    }
+}
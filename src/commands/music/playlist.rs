@@ -0,0 +1,464 @@
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::model::Timestamp;
+use serenity::prelude::*;
+use tracing::{info, warn};
+
+use crate::commands::music::metadata::TrackMetadata;
+use crate::commands::music::play::{
+    enqueue_known, join_channel_if_needed, resolve_metadata, EnqueueError,
+};
+use crate::commands::utils::{
+    can_manage_guild, get_config, get_filters, get_guild_id_from_message, get_guild_settings, get_track_cache,
+    get_ytdlp, send_error_message, send_success_message, send_warning, to_time,
+};
+use crate::playlists::{Playlist, PlaylistScope, PlaylistStore};
+use crate::ytdlp::YtDlpError;
+use crate::PlaylistStoreKey;
+
+const MAX_NAME_LENGTH: usize = 32;
+
+// Number of tracks listed by `playlist list <name>`
+const PLAYLIST_PAGE_SIZE: usize = 15;
+
+const USAGE: &str = "Use the command like this: playlist <save|load|add|remove|list|delete> ...";
+
+async fn get_store(ctx: &Context) -> PlaylistStore {
+    let data = ctx.data.read().await;
+    data.get::<PlaylistStoreKey>()
+        .cloned()
+        .expect("Should exist in typemap")
+}
+
+// `save server <name>` creates a playlist shared with the guild, otherwise it
+// belongs to the member
+fn parse_scope(args: &mut Args, msg: &Message, guild_id: GuildId) -> (PlaylistScope, Option<String>) {
+    let first = args.single::<String>().ok();
+    match first.as_deref() {
+        Some("server") if !args.is_empty() => (
+            PlaylistScope::Guild(guild_id),
+            args.single::<String>().ok(),
+        ),
+        _ => (PlaylistScope::User(msg.author.id), first),
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().count() <= MAX_NAME_LENGTH
+}
+
+// Personal playlists are only ever found for their owner; guild playlists can
+// be changed by whoever made them and by server managers
+async fn can_edit(ctx: &Context, msg: &Message, playlist: &Playlist) -> bool {
+    match playlist.scope {
+        PlaylistScope::User(owner_id) => owner_id == msg.author.id,
+        PlaylistScope::Guild(_) => {
+            playlist.owner_id == msg.author.id || can_manage_guild(ctx, msg).await
+        }
+    }
+}
+
+// Sends the reason to the channel and returns `None` when nothing is found
+async fn find_playlist(
+    ctx: &Context,
+    msg: &Message,
+    guild_id: GuildId,
+    name: &str,
+) -> CommandResult<Option<Playlist>> {
+    match get_store(ctx).await.find(msg.author.id, guild_id, name).await {
+        Ok(Some(playlist)) => Ok(Some(playlist)),
+        Ok(None) => {
+            send_warning(ctx, msg, &format!("There is no playlist named `{}`.", name)).await?;
+            Ok(None)
+        }
+        Err(e) => {
+            warn!("playlist: Failed to look up '{}': {}", name, e);
+            send_error_message(ctx, msg, "Could not read saved playlists.").await?;
+            Ok(None)
+        }
+    }
+}
+
+#[command]
+#[aliases(pl)]
+#[only_in(guilds)]
+#[sub_commands(playlist_save, playlist_load, playlist_add, playlist_remove, playlist_list, playlist_delete)]
+async fn playlist(ctx: &Context, msg: &Message) -> CommandResult {
+    send_warning(ctx, msg, USAGE).await
+}
+
+#[command("save")]
+#[only_in(guilds)]
+async fn playlist_save(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let (scope, name) = parse_scope(&mut args, msg, guild_id);
+    let Some(name) = name.filter(|name| valid_name(name)) else {
+        send_warning(ctx, msg, "Use the command like this: playlist save [server] <name>").await?;
+        return Ok(());
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let tracks: Vec<TrackMetadata> = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock
+            .lock()
+            .await
+            .queue()
+            .current_queue()
+            .iter()
+            .map(|handle| TrackMetadata::clone(&handle.data::<TrackMetadata>()))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    if tracks.is_empty() {
+        send_warning(ctx, msg, "The queue is empty, there is nothing to save.").await?;
+        return Ok(());
+    }
+
+    let store = get_store(ctx).await;
+    let existing = match store.get(scope, &name).await {
+        Ok(existing) => existing,
+        Err(e) => {
+            warn!("playlist_save: Failed to look up '{}': {}", name, e);
+            send_error_message(ctx, msg, "Could not read saved playlists.").await?;
+            return Ok(());
+        }
+    };
+
+    if let Some(existing) = &existing {
+        if !can_edit(ctx, msg, existing).await {
+            send_warning(ctx, msg, "Only its creator or a server manager can replace that playlist.").await?;
+            return Ok(());
+        }
+    }
+
+    let count = tracks.len();
+    if let Err(e) = store.save(scope, msg.author.id, &name, tracks).await {
+        warn!("playlist_save: Failed to save '{}': {}", name, e);
+        send_error_message(ctx, msg, "Could not save the playlist.").await?;
+        return Ok(());
+    }
+
+    info!("playlist_save: {} saved '{}' with {} tracks", msg.author.name, name, count);
+    let verb = if existing.is_some() { "Updated" } else { "Saved" };
+    send_success_message(
+        ctx,
+        msg,
+        &format!(":floppy_disk: {} playlist **{}** with {} tracks", verb, name, count),
+    )
+    .await
+}
+
+#[command("load")]
+#[only_in(guilds)]
+async fn playlist_load(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let Ok(name) = args.single::<String>() else {
+        send_warning(ctx, msg, "Use the command like this: playlist load <name>").await?;
+        return Ok(());
+    };
+
+    let Some(playlist) = find_playlist(ctx, msg, guild_id, &name).await? else {
+        return Ok(());
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if manager.get(guild_id).is_none() {
+        if let Err(err_msg) = join_channel_if_needed(ctx, msg).await {
+            send_error_message(ctx, msg, &err_msg).await?;
+            return Ok(());
+        }
+    }
+
+    let Some(handler_lock) = manager.get(guild_id) else {
+        send_error_message(ctx, msg, "Not in a voice channel.").await?;
+        return Ok(());
+    };
+
+    let ytdlp = get_ytdlp(ctx).await;
+    let config = get_config(ctx).await;
+    let settings = get_guild_settings(ctx, guild_id).await;
+    let cache = get_track_cache(ctx).await;
+    let filters = get_filters(ctx, guild_id).await;
+
+    let mut handler = handler_lock.lock().await;
+
+    let queue_space = match settings.max_queue_length(config) {
+        0 => usize::MAX,
+        max => max.saturating_sub(handler.queue().len()),
+    };
+    let total = playlist.tracks.len();
+
+    let mut added = 0;
+    let mut too_long = 0;
+    for mut metadata in playlist.tracks.into_iter().take(queue_space) {
+        metadata.requester_id = msg.author.id;
+        metadata.requester_name = msg.author.name.clone();
+        metadata.autoplay = false;
+
+        match enqueue_known(
+            &mut handler,
+            &ytdlp,
+            metadata,
+            &settings,
+            config,
+            &cache,
+            &filters,
+        ) {
+            Ok(_) => added += 1,
+            Err(EnqueueError::TooLong { .. }) => too_long += 1,
+            Err(e) => warn!("playlist_load: Failed to enqueue track: {:?}", e),
+        }
+    }
+    drop(handler);
+
+    info!(
+        "playlist_load: {} loaded '{}', {} of {} tracks queued",
+        msg.author.name, playlist.name, added, total
+    );
+
+    let skipped = total - added;
+    let title = if skipped == 0 {
+        format!(":notes: Loaded playlist **{}**, {} tracks added.", playlist.name, added)
+    } else {
+        format!(
+            ":warning: Loaded playlist **{}**, {} tracks added. {} skipped ({} over the length limit, the rest due to queue limits).",
+            playlist.name, added, skipped, too_long
+        )
+    };
+    send_success_message(ctx, msg, &title).await
+}
+
+#[command("add")]
+#[only_in(guilds)]
+async fn playlist_add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let name = args.single::<String>().ok().filter(|name| valid_name(name));
+    let query = args.rest().trim();
+    let Some(name) = name.filter(|_| !query.is_empty()) else {
+        send_warning(ctx, msg, "Use the command like this: playlist add <name> <url or song name>").await?;
+        return Ok(());
+    };
+
+    let store = get_store(ctx).await;
+    let playlist = match store.find(msg.author.id, guild_id, &name).await {
+        Ok(playlist) => playlist,
+        Err(e) => {
+            warn!("playlist_add: Failed to look up '{}': {}", name, e);
+            send_error_message(ctx, msg, "Could not read saved playlists.").await?;
+            return Ok(());
+        }
+    };
+
+    if let Some(playlist) = &playlist {
+        if !can_edit(ctx, msg, playlist).await {
+            send_warning(ctx, msg, "Only its creator or a server manager can change that playlist.").await?;
+            return Ok(());
+        }
+    }
+
+    let ytdlp = get_ytdlp(ctx).await;
+    let lookup = if query.starts_with("http") {
+        query.to_string()
+    } else {
+        format!("ytsearch1:{}", query)
+    };
+
+    let metadata = match resolve_metadata(&ytdlp, &lookup, msg.author.id, &msg.author.name).await {
+        Ok(metadata) => metadata,
+        Err(YtDlpError::Failed(e)) => {
+            warn!("playlist_add: Failed to resolve '{}': {}", query, e);
+            send_error_message(ctx, msg, &format!("No results found for: {}", query)).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            warn!("playlist_add: Failed to resolve '{}': {:?}", query, e);
+            send_error_message(ctx, msg, &e.to_string()).await?;
+            return Ok(());
+        }
+    };
+
+    let title = metadata.title.clone();
+    let result = match &playlist {
+        Some(playlist) => store.add_track(playlist.id, metadata).await,
+        // Adding to a playlist that doesn't exist yet creates a personal one
+        None => {
+            store
+                .save(PlaylistScope::User(msg.author.id), msg.author.id, &name, vec![metadata])
+                .await
+        }
+    };
+
+    if let Err(e) = result {
+        warn!("playlist_add: Failed to add to '{}': {}", name, e);
+        send_error_message(ctx, msg, "Could not save the playlist.").await?;
+        return Ok(());
+    }
+
+    let name = playlist.map(|playlist| playlist.name).unwrap_or(name);
+    send_success_message(ctx, msg, &format!(":heavy_plus_sign: Added **{}** to **{}**", title, name)).await
+}
+
+#[command("remove")]
+#[only_in(guilds)]
+async fn playlist_remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let (Ok(name), Ok(position)) = (args.single::<String>(), args.single::<usize>()) else {
+        send_warning(ctx, msg, "Use the command like this: playlist remove <name> <position>").await?;
+        return Ok(());
+    };
+
+    let Some(playlist) = find_playlist(ctx, msg, guild_id, &name).await? else {
+        return Ok(());
+    };
+
+    if !can_edit(ctx, msg, &playlist).await {
+        send_warning(ctx, msg, "Only its creator or a server manager can change that playlist.").await?;
+        return Ok(());
+    }
+
+    let removed = match position.checked_sub(1) {
+        Some(index) => get_store(ctx).await.remove_track(playlist.id, index).await,
+        None => Ok(None),
+    };
+
+    match removed {
+        Ok(Some(metadata)) => {
+            send_success_message(
+                ctx,
+                msg,
+                &format!(":heavy_minus_sign: Removed **{}** from **{}**", metadata.title, playlist.name),
+            )
+            .await
+        }
+        Ok(None) => {
+            send_warning(
+                ctx,
+                msg,
+                &format!("**{}** has no track at position {}.", playlist.name, position),
+            )
+            .await
+        }
+        Err(e) => {
+            warn!("playlist_remove: Failed to change '{}': {}", playlist.name, e);
+            send_error_message(ctx, msg, "Could not save the playlist.").await
+        }
+    }
+}
+
+#[command("list")]
+#[only_in(guilds)]
+async fn playlist_list(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let embed = match args.single::<String>() {
+        Ok(name) => {
+            let Some(playlist) = find_playlist(ctx, msg, guild_id, &name).await? else {
+                return Ok(());
+            };
+
+            let mut lines: Vec<String> = playlist
+                .tracks
+                .iter()
+                .take(PLAYLIST_PAGE_SIZE)
+                .enumerate()
+                .map(|(idx, metadata)| {
+                    let duration = metadata
+                        .duration
+                        .map(|d| to_time(d.as_secs()))
+                        .unwrap_or_else(|| "live".to_string());
+                    format!("`{}.` {} `{}`", idx + 1, metadata.link(), duration)
+                })
+                .collect();
+            if playlist.tracks.len() > PLAYLIST_PAGE_SIZE {
+                lines.push(format!("... and {} more", playlist.tracks.len() - PLAYLIST_PAGE_SIZE));
+            }
+            if lines.is_empty() {
+                lines.push("*empty*".to_string());
+            }
+
+            CreateEmbed::default()
+                .title(format!("{} ({} tracks)", playlist.name, playlist.tracks.len()))
+                .description(lines.join("\n"))
+        }
+        Err(_) => {
+            let playlists = match get_store(ctx).await.list(msg.author.id, guild_id).await {
+                Ok(playlists) => playlists,
+                Err(e) => {
+                    warn!("playlist_list: Failed to list playlists: {}", e);
+                    send_error_message(ctx, msg, "Could not read saved playlists.").await?;
+                    return Ok(());
+                }
+            };
+
+            if playlists.is_empty() {
+                send_warning(ctx, msg, "There are no saved playlists yet.").await?;
+                return Ok(());
+            }
+
+            let lines: Vec<String> = playlists
+                .iter()
+                .map(|summary| {
+                    let scope = match summary.scope {
+                        PlaylistScope::User(_) => "personal",
+                        PlaylistScope::Guild(_) => "server",
+                    };
+                    format!("**{}** - {} tracks *({})*", summary.name, summary.track_count, scope)
+                })
+                .collect();
+
+            CreateEmbed::default()
+                .title("Saved playlists")
+                .description(lines.join("\n"))
+        }
+    };
+
+    let embed = embed.color(0xffffff).timestamp(Timestamp::now());
+    msg.channel_id
+        .send_message(&ctx.http, CreateMessage::default().add_embed(embed))
+        .await?;
+
+    Ok(())
+}
+
+#[command("delete")]
+#[only_in(guilds)]
+async fn playlist_delete(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let Ok(name) = args.single::<String>() else {
+        send_warning(ctx, msg, "Use the command like this: playlist delete <name>").await?;
+        return Ok(());
+    };
+
+    let Some(playlist) = find_playlist(ctx, msg, guild_id, &name).await? else {
+        return Ok(());
+    };
+
+    if !can_edit(ctx, msg, &playlist).await {
+        send_warning(ctx, msg, "Only its creator or a server manager can delete that playlist.").await?;
+        return Ok(());
+    }
+
+    if let Err(e) = get_store(ctx).await.delete(playlist.id).await {
+        warn!("playlist_delete: Failed to delete '{}': {}", playlist.name, e);
+        send_error_message(ctx, msg, "Could not delete the playlist.").await?;
+        return Ok(());
+    }
+
+    info!("playlist_delete: {} deleted '{}'", msg.author.name, playlist.name);
+    send_success_message(ctx, msg, &format!(":wastebasket: Deleted playlist **{}**", playlist.name)).await
+}
@@ -0,0 +1,103 @@
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tracing::{info, warn};
+
+use crate::commands::music::play::enqueue_with_metadata;
+use crate::commands::utils::{
+    get_config, get_filters, get_guild_id_from_message, get_guild_settings, get_track_cache, get_ytdlp,
+    send_error_message, send_success_message, send_warning,
+};
+use crate::TrackHistoryKey;
+
+#[command]
+#[aliases(back)]
+#[only_in(guilds)]
+async fn previous(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let manager = match songbird::get(ctx).await {
+        Some(manager) => manager,
+        None => {
+            send_error_message(ctx, msg, "Songbird client missing.").await?;
+            return Ok(());
+        }
+    };
+
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            send_error_message(ctx, msg, "Not in a voice channel.").await?;
+            return Ok(());
+        }
+    };
+
+    let history = {
+        let data = ctx.data.read().await;
+        data.get::<TrackHistoryKey>()
+            .cloned()
+            .expect("Should exist in typemap")
+    };
+
+    let metadata = match history.pop_latest(guild_id).await {
+        Some(metadata) => metadata,
+        None => {
+            send_warning(ctx, msg, "There is no previous track.").await?;
+            return Ok(());
+        }
+    };
+
+    let ytdlp = get_ytdlp(ctx).await;
+    let config = get_config(ctx).await;
+    let settings = get_guild_settings(ctx, guild_id).await;
+    let filters = get_filters(ctx, guild_id).await;
+    let mut handler = handler_lock.lock().await;
+
+    let source = get_track_cache(ctx).await.input(&metadata, &ytdlp);
+    let handle = enqueue_with_metadata(
+        &mut handler,
+        source,
+        metadata.clone(),
+        settings.default_volume(config),
+        &filters,
+    );
+
+    // The replayed track was appended to the queue; move it in front of the
+    // current one, which stays paused at its position until the replay ends.
+    let queue = handler.queue();
+    let moved = queue.modify_queue(|tracks| {
+        if tracks.len() < 2 {
+            return false;
+        }
+
+        match tracks.pop_back() {
+            Some(replayed) => {
+                if let Some(current) = tracks.front() {
+                    let _ = current.pause();
+                }
+                tracks.push_front(replayed);
+                true
+            }
+            None => false,
+        }
+    });
+
+    if moved {
+        if let Err(e) = handle.play() {
+            warn!("previous: Failed to start replayed track: {}", e);
+            send_error_message(ctx, msg, "Error starting the previous track.").await?;
+            return Ok(());
+        }
+    }
+
+    info!("previous: Replaying '{}' in guild {:?}", metadata.title, guild_id);
+    send_success_message(
+        ctx,
+        msg,
+        &format!(":track_previous: Playing again: **{}**", metadata.title),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,368 @@
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAttachment, CreateEmbed, CreateMessage};
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::prelude::*;
+use serenity::model::Timestamp;
+use serenity::prelude::*;
+use tracing::{info, warn};
+
+use crate::commands::music::metadata::{TrackMetadata, TrackSource};
+use crate::commands::music::play::{enqueue_query, join_channel_if_needed};
+use crate::commands::music::resolver::TrackQuery;
+use crate::commands::utils::{
+    get_config, get_filters, get_guild_id_from_message, get_guild_settings, get_resolvers,
+    send_error_message,
+    send_warning, to_time,
+};
+
+// Number of queue entries listed in the embed
+const QUEUE_PAGE_SIZE: usize = 10;
+
+// Largest attachment `queue import` downloads
+const MAX_IMPORT_SIZE: u32 = 1024 * 1024;
+
+// Number of failed entries listed after an import
+const IMPORT_FAILURES_SHOWN: usize = 10;
+
+#[command]
+#[aliases(q)]
+#[only_in(guilds)]
+#[sub_commands(queue_export, queue_import)]
+async fn queue(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let songbird_client = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    let tracks = match songbird_client.get(guild_id) {
+        Some(handler_lock) => {
+            let handler = handler_lock.lock().await;
+            handler.queue().current_queue()
+        }
+        None => {
+            send_warning(ctx, msg, "Currently not in a voice channel.").await?;
+            return Ok(());
+        }
+    };
+
+    if tracks.is_empty() {
+        send_warning(ctx, msg, "The queue is empty.").await?;
+        return Ok(());
+    }
+
+    let mut lines: Vec<String> = tracks
+        .iter()
+        .take(QUEUE_PAGE_SIZE)
+        .enumerate()
+        .map(|(idx, handle)| {
+            let metadata = handle.data::<TrackMetadata>();
+            let position = if idx == 0 {
+                ":arrow_forward:".to_string()
+            } else {
+                format!("`{}.`", idx)
+            };
+            let duration = metadata
+                .duration
+                .map(|d| to_time(d.as_secs()))
+                .unwrap_or_else(|| "live".to_string());
+            let origin = if metadata.autoplay {
+                "*autoplay*".to_string()
+            } else {
+                format!("requested by {}", metadata.requester_name)
+            };
+
+            format!(
+                "{} {} `{}` - {}",
+                position,
+                metadata.link(),
+                duration,
+                origin
+            )
+        })
+        .collect();
+
+    if tracks.len() > QUEUE_PAGE_SIZE {
+        lines.push(format!("... and {} more", tracks.len() - QUEUE_PAGE_SIZE));
+    }
+
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(format!("Queue ({} tracks)", tracks.len()))
+        .description(lines.join("\n"))
+        .timestamp(Timestamp::now());
+
+    let builder = CreateMessage::default().add_embed(embed);
+    msg.channel_id.send_message(&ctx.http, builder).await?;
+
+    Ok(())
+}
+
+// The JSON written by `queue export` and read by `queue import`
+#[derive(Serialize, Deserialize)]
+struct QueueExport {
+    guild_id: GuildId,
+    exported_at: Timestamp,
+    tracks: Vec<QueueEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QueueEntry {
+    url: String,
+    title: String,
+    // Seconds, missing for live streams
+    duration: Option<u64>,
+    requester_id: Option<UserId>,
+    requester_name: Option<String>,
+}
+
+impl From<&TrackMetadata> for QueueEntry {
+    fn from(metadata: &TrackMetadata) -> Self {
+        let url = match metadata.source {
+            TrackSource::File => library_query(&metadata.title),
+            _ => metadata.url.clone(),
+        };
+
+        Self {
+            url,
+            title: metadata.title.clone(),
+            duration: metadata.duration.map(|d| d.as_secs()),
+            requester_id: Some(metadata.requester_id),
+            requester_name: Some(metadata.requester_name.clone()),
+        }
+    }
+}
+
+// Library files are exported as a search of the library, which `queue import`
+// runs again, rather than as their path on this server
+fn library_query(title: &str) -> String {
+    let words: Vec<&str> = title.split_whitespace().filter(|word| *word != "-").collect();
+    format!("local:{}", words.join(" "))
+}
+
+fn to_m3u8(entries: &[QueueEntry]) -> String {
+    let mut playlist = String::from("#EXTM3U\n");
+    for entry in entries {
+        let duration = entry.duration.map(|d| d as i64).unwrap_or(-1);
+        let title = entry.title.replace(['\r', '\n'], " ");
+        playlist.push_str(&format!("#EXTINF:{},{}\n{}\n", duration, title, entry.url));
+    }
+
+    playlist
+}
+
+// Reads either our JSON export or an M3U / M3U8 playlist
+fn parse_import(contents: &str) -> Result<Vec<QueueEntry>, String> {
+    if contents.trim_start().starts_with('{') {
+        return serde_json::from_str::<QueueExport>(contents)
+            .map(|export| export.tracks)
+            .map_err(|e| format!("The JSON file is not a queue export: {}", e));
+    }
+
+    let mut entries = Vec::new();
+    let mut title = None;
+    for line in contents.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info.split_once(',').map(|(_, title)| title.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push(QueueEntry {
+                url: line.to_string(),
+                title: title.take().unwrap_or_else(|| line.to_string()),
+                duration: None,
+                requester_id: None,
+                requester_name: None,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+#[command("export")]
+#[only_in(guilds)]
+async fn queue_export(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let songbird_client = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    let entries: Vec<QueueEntry> = match songbird_client.get(guild_id) {
+        Some(handler_lock) => handler_lock
+            .lock()
+            .await
+            .queue()
+            .current_queue()
+            .iter()
+            .map(|handle| QueueEntry::from(&*handle.data::<TrackMetadata>()))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    if entries.is_empty() {
+        send_warning(ctx, msg, "The queue is empty, there is nothing to export.").await?;
+        return Ok(());
+    }
+
+    let m3u8 = to_m3u8(&entries);
+    let export = QueueExport {
+        guild_id,
+        exported_at: Timestamp::now(),
+        tracks: entries,
+    };
+    let json = serde_json::to_vec_pretty(&export)?;
+
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(format!(":outbox_tray: Exported {} tracks", export.tracks.len()))
+        .description("Load them again with `queue import` and one of these files attached.")
+        .timestamp(Timestamp::now());
+    let builder = CreateMessage::default()
+        .add_embed(embed)
+        .add_file(CreateAttachment::bytes(m3u8, "queue.m3u8"))
+        .add_file(CreateAttachment::bytes(json, "queue.json"));
+    msg.channel_id.send_message(&ctx.http, builder).await?;
+
+    Ok(())
+}
+
+#[command("import")]
+#[only_in(guilds)]
+async fn queue_import(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let Some(attachment) = msg.attachments.first() else {
+        send_warning(ctx, msg, "Attach a .m3u8 or .json file exported with `queue export`.").await?;
+        return Ok(());
+    };
+
+    if attachment.size > MAX_IMPORT_SIZE {
+        send_warning(ctx, msg, "That file is too large to import.").await?;
+        return Ok(());
+    }
+
+    let contents = match attachment.download().await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            warn!("queue_import: Failed to download {}: {}", attachment.url, e);
+            send_error_message(ctx, msg, "Could not download the attachment.").await?;
+            return Ok(());
+        }
+    };
+
+    let mut entries = match parse_import(&contents) {
+        Ok(entries) if !entries.is_empty() => entries,
+        Ok(_) => {
+            send_warning(ctx, msg, "The file contains no tracks.").await?;
+            return Ok(());
+        }
+        Err(reason) => {
+            send_warning(ctx, msg, &reason).await?;
+            return Ok(());
+        }
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if manager.get(guild_id).is_none() {
+        if let Err(err_msg) = join_channel_if_needed(ctx, msg).await {
+            send_error_message(ctx, msg, &err_msg).await?;
+            return Ok(());
+        }
+    }
+
+    let Some(handler_lock) = manager.get(guild_id) else {
+        send_error_message(ctx, msg, "Not in a voice channel.").await?;
+        return Ok(());
+    };
+
+    let config = get_config(ctx).await;
+    let settings = get_guild_settings(ctx, guild_id).await;
+    let resolvers = get_resolvers(ctx).await;
+    let filters = get_filters(ctx, guild_id).await;
+
+    // An import counts as a playlist for the queue limits
+    let queue_length = handler_lock.lock().await.queue().len();
+    let queue_space = match settings.max_queue_length(config) {
+        0 => usize::MAX,
+        max => max.saturating_sub(queue_length),
+    };
+    let playlist_space = match settings.max_playlist_length(config) {
+        0 => usize::MAX,
+        max => max,
+    };
+    let mut skipped = entries.len().saturating_sub(queue_space.min(playlist_space));
+    entries.truncate(entries.len() - skipped);
+    let total = entries.len() + skipped;
+
+    let mut added = 0;
+    let mut failures = Vec::new();
+    for entry in &entries {
+        if !entry.url.starts_with("http") && !entry.url.starts_with("local:") {
+            failures.push(format!("{} - not a URL", entry.title));
+            continue;
+        }
+
+        let query = TrackQuery::Text(&entry.url);
+        match enqueue_query(&handler_lock, &resolvers, &query, &msg.author, &settings, config, &filters)
+            .await
+        {
+            Ok(enqueued) => {
+                added += enqueued.tracks.len();
+                skipped += enqueued.queue_full;
+                if enqueued.too_long > 0 {
+                    failures.push(format!(
+                        "[{}]({}) - {} tracks over the length limit",
+                        entry.title, entry.url, enqueued.too_long
+                    ));
+                }
+            }
+            Err(e) => {
+                warn!("queue_import: Failed to enqueue {}: {:?}", entry.url, e);
+                failures.push(format!("[{}]({}) - {}", entry.title, entry.url, e));
+            }
+        }
+    }
+
+    info!(
+        "queue_import: {} imported {} of {} tracks in guild {:?}",
+        msg.author.name,
+        added,
+        total,
+        guild_id
+    );
+
+    let mut lines: Vec<String> = failures
+        .iter()
+        .take(IMPORT_FAILURES_SHOWN)
+        .cloned()
+        .collect();
+    if failures.len() > IMPORT_FAILURES_SHOWN {
+        lines.push(format!("... and {} more", failures.len() - IMPORT_FAILURES_SHOWN));
+    }
+    if skipped > 0 {
+        lines.push(format!("{} tracks skipped due to queue limits.", skipped));
+    }
+
+    let title = if failures.is_empty() && skipped == 0 {
+        format!(":inbox_tray: Imported {} tracks", added)
+    } else {
+        format!(":warning: Imported {} tracks, {} failed", added, failures.len())
+    };
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(title)
+        .description(lines.join("\n"))
+        .timestamp(Timestamp::now());
+    msg.channel_id
+        .send_message(&ctx.http, CreateMessage::default().add_embed(embed))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,252 @@
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::model::Timestamp;
+use serenity::prelude::*;
+use tracing::{info, warn};
+
+use crate::commands::music::play::{enqueue_known, join_channel_if_needed};
+use crate::commands::music::stream::probe_direct_media;
+use crate::commands::utils::{
+    get_config, get_crossfades, get_filters, get_guild_id_from_message, get_guild_settings, get_prefix, get_track_cache, get_ytdlp,
+    send_error_message, send_success_message, send_warning,
+};
+use crate::radio::{RadioStation, RadioStore};
+use crate::{HttpKey, RadioStoreKey};
+
+const MAX_NAME_LENGTH: usize = 32;
+
+// Station names that would be taken for a subcommand
+const RESERVED_NAMES: &[&str] = &["add", "remove", "list"];
+
+// `radio <name> --queue` adds the station behind the queue instead of
+// replacing it
+const QUEUE_FLAG: &str = "--queue";
+
+async fn get_store(ctx: &Context) -> RadioStore {
+    let data = ctx.data.read().await;
+    data.get::<RadioStoreKey>()
+        .cloned()
+        .expect("Should exist in typemap")
+}
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(radio_add, radio_remove, radio_list)]
+async fn radio(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let mut words: Vec<&str> = args.rest().split_whitespace().collect();
+    let enqueue = words.contains(&QUEUE_FLAG);
+    words.retain(|word| *word != QUEUE_FLAG);
+
+    let [name] = words[..] else {
+        let prefix = get_prefix(ctx, msg).await;
+        send_warning(
+            ctx,
+            msg,
+            &format!(
+                "Use the command like this: radio <name> [{}], see `{}radio list` for stations",
+                QUEUE_FLAG, prefix
+            ),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let station = match get_store(ctx).await.get(guild_id, name).await {
+        Ok(Some(station)) => station,
+        Ok(None) => {
+            send_warning(ctx, msg, &format!("No station called **{}**.", name)).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            warn!("radio: Failed to look up '{}': {}", name, e);
+            send_error_message(ctx, msg, "Could not read radio stations.").await?;
+            return Ok(());
+        }
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if manager.get(guild_id).is_none() {
+        if let Err(err_msg) = join_channel_if_needed(ctx, msg).await {
+            send_error_message(ctx, msg, &err_msg).await?;
+            return Ok(());
+        }
+    }
+
+    let Some(handler_lock) = manager.get(guild_id) else {
+        send_error_message(ctx, msg, "Not in a voice channel.").await?;
+        return Ok(());
+    };
+
+    let ytdlp = get_ytdlp(ctx).await;
+    let config = get_config(ctx).await;
+    let settings = get_guild_settings(ctx, guild_id).await;
+    let cache = get_track_cache(ctx).await;
+    let filters = get_filters(ctx, guild_id).await;
+
+    let mut handler = handler_lock.lock().await;
+
+    if enqueue {
+        let max_queue_length = settings.max_queue_length(config);
+        if max_queue_length > 0 && handler.queue().len() >= max_queue_length {
+            send_error_message(
+                ctx,
+                msg,
+                &format!("The queue is full ({} tracks).", max_queue_length),
+            )
+            .await?;
+            return Ok(());
+        }
+    } else {
+        handler.queue().stop();
+        get_crossfades(ctx).await.stop(guild_id);
+    }
+
+    let metadata = station.to_metadata(msg.author.id, &msg.author.name);
+    if let Err(e) = enqueue_known(&mut handler, &ytdlp, metadata, &settings, config, &cache, &filters) {
+        warn!("radio: Failed to enqueue '{}': {:?}", station.name, e);
+        send_error_message(ctx, msg, &e.to_string()).await?;
+        return Ok(());
+    }
+    drop(handler);
+
+    info!(
+        "radio: {} tuned in to '{}' in guild {:?} (queued: {})",
+        msg.author.name, station.name, guild_id, enqueue
+    );
+
+    let title = if enqueue {
+        format!(":radio: Added **{}** to the queue", station.name)
+    } else {
+        format!(":radio: Tuned in to **{}**", station.name)
+    };
+    send_success_message(ctx, msg, &title).await
+}
+
+#[command("add")]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_GUILD)]
+async fn radio_add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let name = args.single::<String>().ok();
+    let url = args.single::<String>().ok().filter(|url| url.starts_with("http"));
+    let (Some(name), Some(url)) = (name, url) else {
+        send_warning(ctx, msg, "Use the command like this: radio add <name> <url>").await?;
+        return Ok(());
+    };
+
+    if name.chars().count() > MAX_NAME_LENGTH
+        || RESERVED_NAMES.contains(&name.to_lowercase().as_str())
+        || name.starts_with('-')
+    {
+        send_warning(
+            ctx,
+            msg,
+            &format!(
+                "Station names may be up to {} characters and can't be {}.",
+                MAX_NAME_LENGTH,
+                RESERVED_NAMES.join(", ")
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let http_client = {
+        let data = ctx.data.read().await;
+        data.get::<HttpKey>()
+            .cloned()
+            .expect("Should exist in typemap")
+    };
+
+    // Streams yt-dlp would only pass through are played directly
+    let direct = probe_direct_media(&http_client, &url).await.is_some();
+    let station = RadioStation {
+        name: name.clone(),
+        url,
+        direct,
+    };
+
+    if let Err(e) = get_store(ctx).await.save(guild_id, station).await {
+        warn!("radio_add: Failed to save '{}': {}", name, e);
+        send_error_message(ctx, msg, "Could not save the station.").await?;
+        return Ok(());
+    }
+
+    info!("radio_add: {} saved station '{}' in guild {:?}", msg.author.name, name, guild_id);
+    send_success_message(ctx, msg, &format!(":radio: Saved station **{}**", name)).await
+}
+
+#[command("remove")]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_GUILD)]
+async fn radio_remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let Ok(name) = args.single::<String>() else {
+        send_warning(ctx, msg, "Use the command like this: radio remove <name>").await?;
+        return Ok(());
+    };
+
+    match get_store(ctx).await.delete(guild_id, &name).await {
+        Ok(true) => {
+            info!("radio_remove: {} removed station '{}' in guild {:?}", msg.author.name, name, guild_id);
+            send_success_message(ctx, msg, &format!(":wastebasket: Removed station **{}**", name)).await
+        }
+        Ok(false) => send_warning(ctx, msg, &format!("No station called **{}**.", name)).await,
+        Err(e) => {
+            warn!("radio_remove: Failed to delete '{}': {}", name, e);
+            send_error_message(ctx, msg, "Could not remove the station.").await
+        }
+    }
+}
+
+#[command("list")]
+#[only_in(guilds)]
+async fn radio_list(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let stations = match get_store(ctx).await.list(guild_id).await {
+        Ok(stations) => stations,
+        Err(e) => {
+            warn!("radio_list: Failed to list stations: {}", e);
+            send_error_message(ctx, msg, "Could not read radio stations.").await?;
+            return Ok(());
+        }
+    };
+
+    if stations.is_empty() {
+        let prefix = get_prefix(ctx, msg).await;
+        send_warning(
+            ctx,
+            msg,
+            &format!("No stations saved yet, add one with `{}radio add <name> <url>`.", prefix),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let lines: Vec<String> = stations
+        .iter()
+        .map(|station| format!("**{}** - {}", station.name, station.url))
+        .collect();
+
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(format!(":radio: Radio stations ({})", stations.len()))
+        .description(lines.join("\n"))
+        .timestamp(Timestamp::now());
+    msg.channel_id
+        .send_message(&ctx.http, CreateMessage::default().add_embed(embed))
+        .await?;
+
+    Ok(())
+}
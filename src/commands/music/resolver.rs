@@ -0,0 +1,560 @@
+use std::fmt;
+
+use reqwest::Client as HttpClient;
+use serenity::async_trait;
+use serenity::model::prelude::*;
+use songbird::input::{File, HttpRequest, Input};
+use tokio::task::JoinSet;
+use tracing::{debug, warn};
+
+use crate::cache::TrackCache;
+use crate::commands::music::attachment::{is_audio_attachment, read_audio_file, AUDIO_EXTENSIONS};
+use crate::commands::music::links::{find_on_youtube, LinkMetadata, StreamingLink, MAX_LINKED_TRACKS};
+use crate::commands::music::metadata::{StreamTitle, TrackMetadata, TrackSource};
+use crate::commands::music::play::resolve_track;
+use crate::commands::music::search::SearchProvider;
+use crate::commands::music::stream::{probe_direct_media, DirectMedia};
+use crate::config::Config;
+use crate::filters::PlaybackProgress;
+use crate::library::Library;
+use crate::ytdlp::{YtDlp, YtDlpError};
+
+// Turns what a member asked `play` for into tracks ready for the queue. Each
+// resolver handles one kind of input; the registry asks them in order and
+// the first that recognises the input answers for it.
+
+// What a member asked for
+pub enum TrackQuery<'a> {
+    // A URL, search terms or `local:<query>`
+    Text(&'a str),
+    Attachment(&'a Attachment),
+}
+
+pub struct ResolvedTrack {
+    pub metadata: TrackMetadata,
+    pub input: Input,
+}
+
+#[derive(Debug)]
+pub enum ResolveError {
+    // The input can't be played here, e.g. a file that is too large
+    Rejected(String),
+    // Nothing playable was found, with the reason for the member
+    Failed(String),
+    YtDlp(YtDlpError),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::Rejected(reason) | ResolveError::Failed(reason) => write!(f, "{}", reason),
+            ResolveError::YtDlp(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[async_trait]
+pub trait TrackResolver: Send + Sync {
+    // Shown in logs
+    fn name(&self) -> &'static str;
+
+    // `None` if the query isn't one for this resolver, so the next one is
+    // asked. An album or playlist yields at most `max_tracks`, 0 for no limit.
+    async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        max_tracks: usize,
+    ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>>;
+}
+
+#[derive(Default)]
+pub struct ResolverRegistry {
+    resolvers: Vec<Box<dyn TrackResolver>>,
+}
+
+impl ResolverRegistry {
+    // Asked after every resolver added before it
+    pub fn with(mut self, resolver: impl TrackResolver + 'static) -> Self {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+
+    // Everything `play` understands. yt-dlp comes last since it accepts any
+    // URL and any search.
+    pub fn standard(
+        config: &'static Config,
+        http_client: HttpClient,
+        ytdlp: YtDlp,
+        cache: TrackCache,
+        library: Option<Library>,
+    ) -> Self {
+        Self::default()
+            .with(AttachmentResolver {
+                http_client: http_client.clone(),
+                max_size: config.limits.max_attachment_size,
+            })
+            .with(LibraryResolver { library })
+            .with(StreamingLinkResolver {
+                links: LinkMetadata::new(http_client.clone(), config.link_metadata_url.clone()),
+                ytdlp: ytdlp.clone(),
+            })
+            .with(DirectMediaResolver { http_client })
+            .with(YtDlpSearchResolver { ytdlp: ytdlp.clone() })
+            .with(YtDlpUrlResolver { ytdlp, cache })
+    }
+
+    pub async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        max_tracks: usize,
+    ) -> Result<Vec<ResolvedTrack>, ResolveError> {
+        for resolver in &self.resolvers {
+            if let Some(result) = resolver.resolve(query, requester, max_tracks).await {
+                debug!("ResolverRegistry: {} took the query", resolver.name());
+                return result;
+            }
+        }
+
+        Err(ResolveError::Failed("Nothing here can play that.".to_string()))
+    }
+}
+
+// Audio files uploaded with the command
+pub struct AttachmentResolver {
+    http_client: HttpClient,
+    // Megabytes, 0 for no limit
+    max_size: u64,
+}
+
+#[async_trait]
+impl TrackResolver for AttachmentResolver {
+    fn name(&self) -> &'static str {
+        "attachment"
+    }
+
+    async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        _max_tracks: usize,
+    ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>> {
+        let TrackQuery::Attachment(attachment) = query else {
+            return None;
+        };
+
+        if !is_audio_attachment(attachment) {
+            return Some(Err(ResolveError::Rejected(format!(
+                "Only {} files can be played.",
+                AUDIO_EXTENSIONS.join(", ")
+            ))));
+        }
+
+        if self.max_size > 0 && u64::from(attachment.size) > self.max_size * 1024 * 1024 {
+            return Some(Err(ResolveError::Rejected(format!(
+                "Audio files may be at most {} MB.",
+                self.max_size
+            ))));
+        }
+
+        let track =
+            read_audio_file(&self.http_client, &attachment.url, &attachment.filename, requester).await;
+        Some(track.map(|track| vec![track]))
+    }
+}
+
+// `local:<query>`, the best match from the library
+pub struct LibraryResolver {
+    library: Option<Library>,
+}
+
+#[async_trait]
+impl TrackResolver for LibraryResolver {
+    fn name(&self) -> &'static str {
+        "library"
+    }
+
+    async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        _max_tracks: usize,
+    ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>> {
+        let TrackQuery::Text(text) = query else {
+            return None;
+        };
+        let search = text.strip_prefix("local:")?.trim();
+
+        let Some(library) = &self.library else {
+            return Some(Err(ResolveError::Rejected(
+                "The local library is not enabled.".to_string(),
+            )));
+        };
+
+        let track = match library.search(search, 1).await {
+            Ok(tracks) => tracks.into_iter().next(),
+            Err(e) => {
+                warn!("LibraryResolver: Search for '{}' failed: {}", search, e);
+                return Some(Err(ResolveError::Failed("Could not search the library.".to_string())));
+            }
+        };
+        let Some(track) = track else {
+            return Some(Err(ResolveError::Failed(format!(
+                "Nothing in the library matches: {}",
+                search
+            ))));
+        };
+
+        Some(Ok(vec![ResolvedTrack {
+            input: File::new(track.path.clone()).into(),
+            metadata: track.to_metadata(requester.id, &requester.name),
+        }]))
+    }
+}
+
+// Plain audio files and radio streams, played without yt-dlp
+pub struct DirectMediaResolver {
+    http_client: HttpClient,
+}
+
+#[async_trait]
+impl TrackResolver for DirectMediaResolver {
+    fn name(&self) -> &'static str {
+        "direct media"
+    }
+
+    async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        _max_tracks: usize,
+    ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>> {
+        let TrackQuery::Text(url) = query else {
+            return None;
+        };
+        if !url.starts_with("http") {
+            return None;
+        }
+
+        let track = match probe_direct_media(&self.http_client, url).await? {
+            DirectMedia::File { name } => {
+                read_audio_file(&self.http_client, url, &name, requester).await
+            }
+            DirectMedia::Stream { name } => Ok(ResolvedTrack {
+                input: HttpRequest::new(self.http_client.clone(), url.to_string()).into(),
+                metadata: TrackMetadata {
+                    title: name.unwrap_or_else(|| url.to_string()),
+                    url: url.to_string(),
+                    duration: None,
+                    requester_id: requester.id,
+                    requester_name: requester.name.clone(),
+                    autoplay: false,
+                    source: TrackSource::Http,
+                    stream_title: StreamTitle::default(),
+                    progress: PlaybackProgress::default(),
+                },
+            }),
+        };
+
+        Some(track.map(|track| vec![track]))
+    }
+}
+
+// Spotify, Apple Music and Deezer links, each track found on YouTube and
+// queued under its original title
+pub struct StreamingLinkResolver {
+    links: LinkMetadata,
+    ytdlp: YtDlp,
+}
+
+#[async_trait]
+impl TrackResolver for StreamingLinkResolver {
+    fn name(&self) -> &'static str {
+        "streaming link"
+    }
+
+    async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        max_tracks: usize,
+    ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>> {
+        let TrackQuery::Text(url) = query else {
+            return None;
+        };
+        let link = StreamingLink::parse(url)?;
+        let service = link.service.name();
+
+        let max_tracks = match max_tracks {
+            0 => MAX_LINKED_TRACKS,
+            max => max.min(MAX_LINKED_TRACKS),
+        };
+        let linked = match self.links.tracks(&link, max_tracks).await {
+            Ok(linked) if !linked.is_empty() => linked,
+            Ok(_) => {
+                return Some(Err(ResolveError::Failed(format!(
+                    "No tracks found behind that {} link.",
+                    service
+                ))))
+            }
+            Err(e) => {
+                warn!("StreamingLinkResolver: Reading {} failed: {}", url, e);
+                return Some(Err(ResolveError::Failed(format!(
+                    "Could not read that {} link.",
+                    service
+                ))));
+            }
+        };
+
+        // Searched side by side, the yt-dlp workers limit how many at once
+        let mut lookups = JoinSet::new();
+        for (idx, track) in linked.into_iter().enumerate() {
+            let ytdlp = self.ytdlp.clone();
+            lookups.spawn(async move {
+                let found = find_on_youtube(&ytdlp, &track).await;
+                (idx, track, found)
+            });
+        }
+
+        let mut found = Vec::new();
+        let mut last_error = None;
+        while let Some(lookup) = lookups.join_next().await {
+            match lookup {
+                Ok((idx, track, Ok(Some(result)))) => found.push((idx, track, result)),
+                Ok((_, track, Ok(None))) => {
+                    debug!("StreamingLinkResolver: No YouTube result for '{}'", track.display_title());
+                }
+                Ok((_, track, Err(e))) => {
+                    warn!("StreamingLinkResolver: Searching '{}' failed: {:?}", track.display_title(), e);
+                    last_error = Some(e);
+                }
+                Err(e) => warn!("StreamingLinkResolver: Lookup task failed: {}", e),
+            }
+        }
+
+        if found.is_empty() {
+            return Some(Err(match last_error {
+                Some(e) => ResolveError::YtDlp(e),
+                None => ResolveError::Failed(format!(
+                    "Nothing from that {} link was found on YouTube.",
+                    service
+                )),
+            }));
+        }
+        found.sort_by_key(|(idx, _, _)| *idx);
+
+        let tracks = found
+            .into_iter()
+            .map(|(_, track, (url, duration))| ResolvedTrack {
+                input: self.ytdlp.source(&url).into(),
+                metadata: TrackMetadata {
+                    title: track.display_title(),
+                    url,
+                    duration: duration.or(track.duration),
+                    requester_id: requester.id,
+                    requester_name: requester.name.clone(),
+                    autoplay: false,
+                    source: TrackSource::YoutubeDl,
+                    stream_title: StreamTitle::default(),
+                    progress: PlaybackProgress::default(),
+                },
+            })
+            .collect();
+
+        Some(Ok(tracks))
+    }
+}
+
+// Search terms, the first result of the provider named by their prefix, or of
+// YouTube
+pub struct YtDlpSearchResolver {
+    ytdlp: YtDlp,
+}
+
+#[async_trait]
+impl TrackResolver for YtDlpSearchResolver {
+    fn name(&self) -> &'static str {
+        "yt-dlp search"
+    }
+
+    async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        _max_tracks: usize,
+    ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>> {
+        let TrackQuery::Text(text) = query else {
+            return None;
+        };
+        if text.starts_with("http") {
+            return None;
+        }
+
+        let (provider, terms) = SearchProvider::split_query(text).unwrap_or((SearchProvider::YouTube, text));
+        let Some(search) = provider.search_query(terms, 1) else {
+            return Some(Err(ResolveError::Rejected(format!(
+                "{} can't be searched, paste a link instead.",
+                provider.name()
+            ))));
+        };
+        let track = match resolve_track(&self.ytdlp, &search, requester.id, &requester.name).await {
+            Ok((metadata, source)) => Ok(ResolvedTrack {
+                input: source.into(),
+                metadata,
+            }),
+            Err(YtDlpError::Failed(e)) => {
+                warn!("YtDlpSearchResolver: Search for '{}' failed: {}", terms, e);
+                Err(ResolveError::Failed(format!("No results found for: {}", terms)))
+            }
+            Err(e) => Err(ResolveError::YtDlp(e)),
+        };
+
+        Some(track.map(|track| vec![track]))
+    }
+}
+
+// Any other URL, for whichever site yt-dlp has an extractor
+pub struct YtDlpUrlResolver {
+    ytdlp: YtDlp,
+    cache: TrackCache,
+}
+
+#[async_trait]
+impl TrackResolver for YtDlpUrlResolver {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        _max_tracks: usize,
+    ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>> {
+        let TrackQuery::Text(url) = query else {
+            return None;
+        };
+        if !url.starts_with("http") {
+            return None;
+        }
+
+        // A cached link needs no yt-dlp at all
+        let (metadata, source) = match self.cache.metadata(url) {
+            Some(mut metadata) => {
+                metadata.requester_id = requester.id;
+                metadata.requester_name = requester.name.clone();
+                metadata.autoplay = false;
+                let source = self.ytdlp.source(&metadata.url);
+                (metadata, source)
+            }
+            None => match resolve_track(&self.ytdlp, url, requester.id, &requester.name).await {
+                Ok(resolved) => resolved,
+                Err(e) => return Some(Err(ResolveError::YtDlp(e))),
+            },
+        };
+
+        let input = self.cache.input_or(&metadata, source);
+        Some(Ok(vec![ResolvedTrack { metadata, input }]))
+    }
+}
+
+// A library file, for tests to fill `FakeResolver` with
+#[cfg(test)]
+pub(crate) fn fake_track(title: &str) -> TrackMetadata {
+    TrackMetadata {
+        title: title.to_string(),
+        url: format!("/music/{}.flac", title),
+        duration: None,
+        requester_id: UserId::new(1),
+        requester_name: String::new(),
+        autoplay: false,
+        source: TrackSource::File,
+        stream_title: StreamTitle::default(),
+        progress: PlaybackProgress::default(),
+    }
+}
+
+// Answers queries from a fixed list of tracks, for tests
+#[cfg(test)]
+pub struct FakeResolver {
+    pub tracks: Vec<(String, TrackMetadata)>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl TrackResolver for FakeResolver {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn resolve(
+        &self,
+        query: &TrackQuery<'_>,
+        requester: &User,
+        _max_tracks: usize,
+    ) -> Option<Result<Vec<ResolvedTrack>, ResolveError>> {
+        let TrackQuery::Text(text) = query else {
+            return None;
+        };
+
+        let tracks: Vec<ResolvedTrack> = self
+            .tracks
+            .iter()
+            .filter(|(key, _)| key == text)
+            .map(|(_, metadata)| {
+                let mut metadata = metadata.clone();
+                metadata.requester_id = requester.id;
+                metadata.requester_name = requester.name.clone();
+                ResolvedTrack {
+                    input: File::new(metadata.url.clone()).into(),
+                    metadata,
+                }
+            })
+            .collect();
+
+        (!tracks.is_empty()).then_some(Ok(tracks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> ResolverRegistry {
+        ResolverRegistry::default()
+            .with(FakeResolver {
+                tracks: vec![("first".to_string(), fake_track("one"))],
+            })
+            .with(FakeResolver {
+                tracks: vec![
+                    ("first".to_string(), fake_track("shadowed")),
+                    ("second".to_string(), fake_track("two")),
+                    ("second".to_string(), fake_track("three")),
+                ],
+            })
+    }
+
+    fn titles(tracks: Vec<ResolvedTrack>) -> Vec<String> {
+        tracks.into_iter().map(|track| track.metadata.title).collect()
+    }
+
+    #[tokio::test]
+    async fn first_resolver_that_knows_the_query_answers() {
+        let requester = User::default();
+
+        let first = registry().resolve(&TrackQuery::Text("first"), &requester, 0).await;
+        assert_eq!(titles(first.unwrap()), vec!["one"]);
+
+        let second = registry().resolve(&TrackQuery::Text("second"), &requester, 0).await;
+        assert_eq!(titles(second.unwrap()), vec!["two", "three"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_query_fails() {
+        let result = registry()
+            .resolve(&TrackQuery::Text("missing"), &User::default(), 0)
+            .await;
+        assert!(matches!(result, Err(ResolveError::Failed(_))));
+    }
+}
@@ -3,7 +3,7 @@ use serenity::framework::standard::CommandResult;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
-use crate::commands::utils::{send_error_message, send_success_message};
+use crate::commands::utils::{get_crossfades, send_error_message, send_success_message};
 
 #[command]
 #[only_in(guilds)]
@@ -29,6 +29,7 @@ async fn resume(ctx: &Context, msg: &Message) -> CommandResult {
         let handler = handler_lock.lock().await;
         let queue = handler.queue();
         let _ = queue.resume();
+        get_crossfades(ctx).await.resume(guild_id);
 
         send_success_message(ctx, msg, ":arrow_forward: Resumed!").await?;
     } else {
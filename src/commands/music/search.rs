@@ -0,0 +1,199 @@
+use reqwest::Url;
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::model::Timestamp;
+use serenity::prelude::*;
+use tracing::{info, warn};
+
+use crate::commands::utils::{
+    get_guild_id_from_message, get_guild_settings, get_prefix, get_ytdlp, send_error_message,
+    send_warning, to_time,
+};
+
+// Where searches are sent, chosen with a `<prefix>:` in front of the terms or
+// per guild with `settings set search_provider`.
+
+// Number of results listed by `search`
+const SEARCH_RESULTS_SHOWN: usize = 5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchProvider {
+    #[default]
+    YouTube,
+    // Songs only, without the intros and skits of music videos
+    YouTubeMusic,
+    SoundCloud,
+    Bandcamp,
+}
+
+pub const PROVIDERS: &[SearchProvider] = &[
+    SearchProvider::YouTube,
+    SearchProvider::YouTubeMusic,
+    SearchProvider::SoundCloud,
+    SearchProvider::Bandcamp,
+];
+
+impl SearchProvider {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            SearchProvider::YouTube => "yt",
+            SearchProvider::YouTubeMusic => "ytm",
+            SearchProvider::SoundCloud => "sc",
+            SearchProvider::Bandcamp => "bc",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SearchProvider::YouTube => "YouTube",
+            SearchProvider::YouTubeMusic => "YouTube Music",
+            SearchProvider::SoundCloud => "SoundCloud",
+            SearchProvider::Bandcamp => "Bandcamp",
+        }
+    }
+
+    pub fn from_prefix(prefix: &str) -> Option<Self> {
+        PROVIDERS
+            .iter()
+            .copied()
+            .find(|provider| provider.prefix().eq_ignore_ascii_case(prefix))
+    }
+
+    // `ytm:some song` into the provider and `some song`
+    pub fn split_query(text: &str) -> Option<(Self, &str)> {
+        let (prefix, terms) = text.split_once(':')?;
+        Some((Self::from_prefix(prefix)?, terms.trim()))
+    }
+
+    // What yt-dlp is given to find the first `count` results. yt-dlp can't
+    // search Bandcamp, links to it still play.
+    pub fn search_query(self, terms: &str, count: usize) -> Option<String> {
+        match self {
+            SearchProvider::YouTube => Some(format!("ytsearch{}:{}", count, terms)),
+            SearchProvider::SoundCloud => Some(format!("scsearch{}:{}", count, terms)),
+            SearchProvider::YouTubeMusic => {
+                let mut url =
+                    Url::parse_with_params("https://music.youtube.com/search", &[("q", terms)])
+                        .expect("Static URL is valid");
+                url.set_fragment(Some("songs"));
+                Some(url.to_string())
+            }
+            SearchProvider::Bandcamp => None,
+        }
+    }
+}
+
+// Puts the guild's provider in front of plain search terms. URLs, library
+// queries and terms that already name a provider are left alone.
+pub fn with_default_provider(text: &str, provider: SearchProvider) -> String {
+    if provider == SearchProvider::YouTube
+        || text.starts_with("http")
+        || text.starts_with("local:")
+        || SearchProvider::split_query(text).is_some()
+    {
+        return text.to_string();
+    }
+
+    format!("{}:{}", provider.prefix(), text)
+}
+
+fn prefixes() -> String {
+    PROVIDERS
+        .iter()
+        .map(|provider| format!("`{}:`", provider.prefix()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[command]
+#[only_in(guilds)]
+async fn search(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let text = args.rest().trim();
+    if text.is_empty() {
+        send_warning(
+            ctx,
+            msg,
+            &format!("Use the command like this: search [{}] <terms>", prefixes()),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let settings = get_guild_settings(ctx, guild_id).await;
+    let (provider, terms) =
+        SearchProvider::split_query(text).unwrap_or((settings.search_provider(), text));
+    let Some(query) = provider.search_query(terms, SEARCH_RESULTS_SHOWN) else {
+        send_warning(
+            ctx,
+            msg,
+            &format!(
+                "{} can't be searched, paste a link instead.",
+                provider.name()
+            ),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let ytdlp = get_ytdlp(ctx).await;
+    let entries = match ytdlp.list(&query, SEARCH_RESULTS_SHOWN).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!(
+                "search: Searching {} for '{}' failed: {:?}",
+                provider.name(),
+                terms,
+                e
+            );
+            send_error_message(ctx, msg, &e.to_string()).await?;
+            return Ok(());
+        }
+    };
+
+    let lines: Vec<String> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let url = entry.url?;
+            let duration = entry
+                .duration
+                .map(|secs| to_time(secs as u64))
+                .unwrap_or_else(|| "?".to_string());
+            Some((entry.title.unwrap_or_else(|| url.clone()), url, duration))
+        })
+        .enumerate()
+        .map(|(idx, (title, url, duration))| {
+            format!("`{}.` [{}]({}) `{}`", idx + 1, title, url, duration)
+        })
+        .collect();
+
+    if lines.is_empty() {
+        send_warning(ctx, msg, &format!("No results found for: {}", terms)).await?;
+        return Ok(());
+    }
+    info!(
+        "search: {} searched {} for '{}'",
+        msg.author.name,
+        provider.name(),
+        terms
+    );
+
+    let prefix = get_prefix(ctx, msg).await;
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(format!(":mag: {} results for: {}", provider.name(), terms))
+        .description(format!(
+            "{}\n\nQueue one with `{}play <link>`.",
+            lines.join("\n"),
+            prefix
+        ))
+        .timestamp(Timestamp::now());
+    msg.channel_id
+        .send_message(&ctx.http, CreateMessage::default().add_embed(embed))
+        .await?;
+
+    Ok(())
+}
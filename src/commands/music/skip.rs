@@ -3,7 +3,7 @@ use serenity::framework::standard::CommandResult;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
-use crate::commands::utils::{send_error_message, send_success_message};
+use crate::commands::utils::{get_crossfades, send_error_message, send_success_message};
 
 #[command]
 #[only_in(guilds)]
@@ -27,8 +27,10 @@ async fn skip(ctx: &Context, msg: &Message) -> CommandResult {
 
     if let Some(handler_lock) = manager.get(guild_id) {
         let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let _ = queue.skip();
+        // Mid crossfade, the song fading out is the one skipped
+        if !get_crossfades(ctx).await.skip(guild_id) {
+            let _ = handler.queue().skip();
+        }
 
         send_success_message(ctx, msg, ":track_next: Skipped!").await?;
     } else {
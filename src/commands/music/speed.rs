@@ -0,0 +1,56 @@
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tracing::info;
+
+use crate::commands::utils::{get_filters, get_guild_id_from_message, get_prefix, send_success_message, send_warning};
+use crate::filters::{MAX_SPEED, MIN_SPEED};
+
+// Plays faster or slower without changing the pitch, from where the current
+// song is and for every song after it, until `speed reset`
+#[command]
+#[only_in(guilds)]
+async fn speed(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+    let filters = get_filters(ctx, guild_id).await;
+
+    let value = args.rest().trim().to_ascii_lowercase();
+    if value.is_empty() {
+        let prefix = get_prefix(ctx, msg).await;
+        let speed = filters.get().speed.unwrap_or(1.0);
+        send_success_message(
+            ctx,
+            msg,
+            &format!(
+                ":control_knobs: Speed is {}x. Change it with `{}speed <{}-{}>` or `{}speed reset`.",
+                speed, prefix, MIN_SPEED, MAX_SPEED, prefix
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let speed = if matches!(value.as_str(), "reset" | "off" | "normal") {
+        1.0
+    } else {
+        match value.trim_end_matches('x').parse::<f64>() {
+            Ok(speed) if (MIN_SPEED..=MAX_SPEED).contains(&speed) => speed,
+            _ => {
+                send_warning(
+                    ctx,
+                    msg,
+                    &format!("The speed must be a number between {} and {}, e.g. `0.75`.", MIN_SPEED, MAX_SPEED),
+                )
+                .await?;
+                return Ok(());
+            }
+        }
+    };
+
+    filters.update(|settings| settings.speed = (speed != 1.0).then_some(speed));
+    info!("speed: {} set the speed in guild {:?} to {}", msg.author.name, guild_id, speed);
+    send_success_message(ctx, msg, &format!(":control_knobs: Speed set to {}x", speed)).await?;
+
+    Ok(())
+}
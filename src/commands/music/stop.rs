@@ -2,7 +2,7 @@ use serenity::model::channel::Message;
 use serenity::{framework::standard::macros::command, client::Context};
 use serenity::framework::standard::CommandResult;
 
-use crate::commands::utils::{send_error_message, send_success_message};
+use crate::commands::utils::{get_crossfades, send_error_message, send_success_message};
 
 #[command]
 #[only_in(guilds)]
@@ -33,6 +33,7 @@ async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
         let handler = handler_lock.lock().await;
         let queue = handler.queue();
         queue.stop();
+        get_crossfades(ctx).await.stop(guild_id);
 
         send_success_message(ctx, msg, ":stop_button: Playlist stopped!").await?;
     } else {
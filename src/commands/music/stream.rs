@@ -0,0 +1,326 @@
+use std::sync::Arc;
+
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::{Client as HttpClient, Url};
+use serenity::async_trait;
+use songbird::events::{Event, EventContext, EventHandler as VoiceEventHandler};
+use songbird::tracks::TrackHandle;
+use songbird::TrackEvent;
+use tokio::sync::Notify;
+use tokio::time::{timeout, Duration};
+use tracing::debug;
+
+use crate::commands::music::attachment::AUDIO_EXTENSIONS;
+use crate::commands::music::metadata::{TrackMetadata, TrackSource};
+
+// Plain audio URLs and internet radio (Icecast / Shoutcast) stations, played
+// straight over HTTP instead of through yt-dlp.
+
+// How long we wait for a server to answer before handing the URL to yt-dlp
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Hosts yt-dlp has extractors for, never worth probing
+const YTDL_HOSTS: &[&str] = &["youtube.com", "youtu.be", "soundcloud.com", "bandcamp.com"];
+
+pub enum DirectMedia {
+    // A file with a known length, read up front for its tags
+    File { name: String },
+    // A live stream such as a radio station
+    Stream { name: Option<String> },
+}
+
+// Decides by extension and response headers whether a URL points at audio
+// we can play directly. Anything else, including servers that don't answer,
+// is left to yt-dlp.
+pub async fn probe_direct_media(http_client: &HttpClient, url: &str) -> Option<DirectMedia> {
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?;
+    if YTDL_HOSTS
+        .iter()
+        .any(|known| host == *known || host.ends_with(&format!(".{}", known)))
+    {
+        return None;
+    }
+
+    let file_name = parsed
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(host)
+        .to_string();
+    let has_audio_extension = file_name.rsplit_once('.').is_some_and(|(_, extension)| {
+        AUDIO_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+    });
+
+    // Only the headers are read, the body is dropped with the response
+    let request = http_client.get(url).header("Icy-MetaData", "1").send();
+    let response = match timeout(PROBE_TIMEOUT, request).await {
+        Ok(Ok(response)) if response.status().is_success() => response,
+        Ok(Ok(response)) => {
+            debug!("probe_direct_media: {} answered {}", url, response.status());
+            return None;
+        }
+        Ok(Err(e)) => {
+            debug!("probe_direct_media: Request to {} failed: {}", url, e);
+            return None;
+        }
+        Err(_) => {
+            debug!("probe_direct_media: {} did not answer in time", url);
+            return None;
+        }
+    };
+
+    let headers = response.headers();
+    let content_type = header_str(headers, CONTENT_TYPE.as_str()).unwrap_or_default();
+    let is_icy = headers.keys().any(|name| name.as_str().starts_with("icy-"));
+    let is_audio = content_type.starts_with("audio/") || content_type == "application/ogg";
+
+    if !(is_icy || is_audio || has_audio_extension) {
+        return None;
+    }
+
+    // Radio servers send no length since the stream never ends
+    if is_icy || !headers.contains_key(CONTENT_LENGTH) {
+        let name = header_str(headers, "icy-name")
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string);
+        Some(DirectMedia::Stream { name })
+    } else {
+        Some(DirectMedia::File { name: file_name })
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+// Follows the song title a radio station announces while one of its streams
+// plays. songbird can't strip ICY metadata from the audio, so the titles are
+// read from a second connection that is dropped once the track ends.
+pub struct StreamTitleWatcher {
+    pub http_client: HttpClient,
+}
+
+#[async_trait]
+impl VoiceEventHandler for StreamTitleWatcher {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(track_list) = ctx else {
+            return None;
+        };
+
+        for (state, handle) in *track_list {
+            let metadata = handle.data::<TrackMetadata>();
+            // `Play` also fires on resume, when the watcher is already running
+            if metadata.source != TrackSource::Http
+                || metadata.duration.is_some()
+                || state.play_time >= Duration::from_secs(1)
+            {
+                continue;
+            }
+
+            let http_client = self.http_client.clone();
+            let handle = TrackHandle::clone(handle);
+            tokio::spawn(async move {
+                if let Err(e) = watch_stream_title(http_client, &handle, &metadata).await {
+                    debug!("StreamTitleWatcher: Stopped for {}: {}", metadata.url, e);
+                }
+            });
+        }
+
+        None
+    }
+}
+
+// Wakes the watcher of a track once the track ends
+struct TrackEnded(Arc<Notify>);
+
+#[async_trait]
+impl VoiceEventHandler for TrackEnded {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        self.0.notify_one();
+        None
+    }
+}
+
+async fn watch_stream_title(
+    http_client: HttpClient,
+    handle: &TrackHandle,
+    metadata: &Arc<TrackMetadata>,
+) -> Result<(), reqwest::Error> {
+    let ended = Arc::new(Notify::new());
+    if handle
+        .add_event(Event::Track(TrackEvent::End), TrackEnded(ended.clone()))
+        .is_err()
+    {
+        return Ok(());
+    }
+
+    tokio::select! {
+        result = read_stream_titles(http_client, handle, metadata) => result,
+        _ = ended.notified() => Ok(()),
+    }
+}
+
+// Returns once the station closes the stream or the track is gone. Stations
+// may send nothing but empty metadata blocks for a long time, so the track is
+// looked at for every chunk rather than every title.
+async fn read_stream_titles(
+    http_client: HttpClient,
+    handle: &TrackHandle,
+    metadata: &Arc<TrackMetadata>,
+) -> Result<(), reqwest::Error> {
+    let mut response = http_client
+        .get(&metadata.url)
+        .header("Icy-MetaData", "1")
+        .send()
+        .await?;
+
+    let Some(metaint) = header_str(response.headers(), "icy-metaint")
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|&metaint| metaint > 0)
+    else {
+        debug!("watch_stream_title: {} sends no titles", metadata.url);
+        return Ok(());
+    };
+
+    let mut parser = IcyParser::new(metaint);
+    while let Some(chunk) = response.chunk().await? {
+        // The track is gone once songbird stops answering for it, e.g. after
+        // the call was left without the track ending
+        if handle.get_info().await.is_err() {
+            return Ok(());
+        }
+
+        for block in parser.push(&chunk) {
+            let title = parse_stream_title(&block);
+            if title != metadata.stream_title.get() {
+                debug!("watch_stream_title: {} now plays {:?}", metadata.url, title);
+                metadata.stream_title.set(title);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Splits an ICY stream into its metadata blocks: `metaint` bytes of audio,
+// then one length byte counting 16 byte units, then that much metadata.
+struct IcyParser {
+    metaint: usize,
+    audio_left: usize,
+    metadata_left: Option<usize>,
+    block: Vec<u8>,
+}
+
+impl IcyParser {
+    fn new(metaint: usize) -> Self {
+        Self {
+            metaint,
+            audio_left: metaint,
+            metadata_left: None,
+            block: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, mut chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut blocks = Vec::new();
+
+        while !chunk.is_empty() {
+            match self.metadata_left {
+                None if self.audio_left > 0 => {
+                    let skipped = self.audio_left.min(chunk.len());
+                    self.audio_left -= skipped;
+                    chunk = &chunk[skipped..];
+                }
+                None => {
+                    self.metadata_left = Some(chunk[0] as usize * 16);
+                    chunk = &chunk[1..];
+                }
+                Some(left) => {
+                    let taken = left.min(chunk.len());
+                    self.block.extend_from_slice(&chunk[..taken]);
+                    chunk = &chunk[taken..];
+                    self.metadata_left = Some(left - taken);
+                }
+            }
+
+            if self.metadata_left == Some(0) {
+                // An empty block means the title hasn't changed
+                if !self.block.is_empty() {
+                    blocks.push(std::mem::take(&mut self.block));
+                }
+                self.metadata_left = None;
+                self.audio_left = self.metaint;
+            }
+        }
+
+        blocks
+    }
+}
+
+// Blocks look like `StreamTitle='Artist - Song';StreamUrl='';`, padded with NULs
+fn parse_stream_title(block: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(block);
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &text[start..];
+    let title = rest.find("';").map_or(rest, |end| &rest[..end]);
+    let title = title.trim_end_matches('\0').trim();
+
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `metaint` bytes of audio, the length byte, then `text` padded to its
+    // 16 byte units
+    fn icy_interval(metaint: usize, text: &str) -> Vec<u8> {
+        let units = text.len().div_ceil(16);
+        let mut bytes = vec![0xAA; metaint];
+        bytes.push(units as u8);
+        bytes.extend_from_slice(text.as_bytes());
+        bytes.resize(metaint + 1 + units * 16, 0);
+        bytes
+    }
+
+    #[test]
+    fn icy_parser_splits_metadata_from_audio() {
+        let mut stream = icy_interval(8, "StreamTitle='One';");
+        stream.extend(icy_interval(8, ""));
+        stream.extend(icy_interval(8, "StreamTitle='Two';StreamUrl='';"));
+        stream.extend([0xAA; 3]);
+
+        let expected = vec![
+            icy_interval(8, "StreamTitle='One';")[9..].to_vec(),
+            icy_interval(8, "StreamTitle='Two';StreamUrl='';")[9..].to_vec(),
+        ];
+
+        // Whole, and in chunks that split the audio, the length byte and the
+        // metadata anywhere
+        assert_eq!(IcyParser::new(8).push(&stream), expected);
+        for size in [1, 5, 9, 17] {
+            let mut parser = IcyParser::new(8);
+            let blocks: Vec<Vec<u8>> = stream.chunks(size).flat_map(|chunk| parser.push(chunk)).collect();
+            assert_eq!(blocks, expected, "in chunks of {}", size);
+        }
+    }
+
+    #[test]
+    fn stream_title_is_read_from_a_block() {
+        let title = |text: &str| parse_stream_title(text.as_bytes());
+
+        assert_eq!(title("StreamTitle='Artist - Song';StreamUrl='';"), Some("Artist - Song".to_string()));
+        assert_eq!(
+            title("StreamTitle='Guns N' Roses - Don't Cry';\0\0\0"),
+            Some("Guns N' Roses - Don't Cry".to_string())
+        );
+        // Cut off before its end
+        assert_eq!(title("StreamTitle='Song\0\0"), Some("Song".to_string()));
+
+        assert_eq!(title("StreamTitle='';StreamUrl='';"), None);
+        assert_eq!(title("StreamTitle='  ';"), None);
+        assert_eq!(title("StreamUrl='http://example.com';"), None);
+    }
+}
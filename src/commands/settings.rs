@@ -0,0 +1,373 @@
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::model::Timestamp;
+use serenity::prelude::*;
+use serenity::utils::{parse_channel_mention, parse_role_mention};
+use tracing::{info, warn};
+
+use crate::commands::music::crossfade::MAX_CROSSFADE;
+use crate::commands::music::search::SearchProvider;
+use crate::commands::utils::{
+    get_config, get_filters, get_guild_id_from_message, get_guild_settings, get_prefix,
+    send_error_message, send_success_message, send_warning,
+};
+use crate::settings::GuildSettings;
+use crate::GuildSettingsKey;
+
+const KEYS: &str = "prefix, dj_role, volume, announce_channel, idle_timeout, \
+                    max_queue_length, max_playlist_length, max_track_duration, autoplay, search_provider, \
+                    normalize, crossfade";
+
+// A parsed `settings set` / `settings reset`, `None` meaning back to the default
+enum Setting {
+    Prefix(Option<String>),
+    DjRole(Option<RoleId>),
+    Volume(Option<f32>),
+    AnnounceChannel(Option<ChannelId>),
+    IdleTimeout(Option<u64>),
+    MaxQueueLength(Option<usize>),
+    MaxPlaylistLength(Option<usize>),
+    MaxTrackDuration(Option<u64>),
+    Autoplay(bool),
+    SearchProvider(Option<SearchProvider>),
+    Normalize(bool),
+    Crossfade(u64),
+}
+
+impl Setting {
+    fn parse(key: &str, value: Option<&str>) -> Result<Self, String> {
+        let setting = match key {
+            "prefix" => Setting::Prefix(value.map(parse_prefix).transpose()?),
+            "dj_role" => Setting::DjRole(
+                value
+                    .map(|v| parse_id(v, parse_role_mention, RoleId::new, "a role"))
+                    .transpose()?,
+            ),
+            "volume" => Setting::Volume(value.map(parse_volume).transpose()?),
+            "announce_channel" => Setting::AnnounceChannel(
+                value
+                    .map(|v| parse_id(v, parse_channel_mention, ChannelId::new, "a channel"))
+                    .transpose()?,
+            ),
+            "idle_timeout" => Setting::IdleTimeout(value.map(parse_number).transpose()?),
+            "max_queue_length" => Setting::MaxQueueLength(value.map(parse_number).transpose()?),
+            "max_playlist_length" => {
+                Setting::MaxPlaylistLength(value.map(parse_number).transpose()?)
+            }
+            "max_track_duration" => {
+                Setting::MaxTrackDuration(value.map(parse_number).transpose()?)
+            }
+            "autoplay" => Setting::Autoplay(match value {
+                None | Some("off") => false,
+                Some("on") => true,
+                Some(_) => return Err("`autoplay` must be `on` or `off`.".to_string()),
+            }),
+            "search_provider" => {
+                Setting::SearchProvider(value.map(parse_search_provider).transpose()?)
+            }
+            "normalize" => Setting::Normalize(match value {
+                None | Some("off") => false,
+                Some("on") => true,
+                Some(_) => return Err("`normalize` must be `on` or `off`.".to_string()),
+            }),
+            "crossfade" => {
+                Setting::Crossfade(value.map(parse_crossfade).transpose()?.unwrap_or(0))
+            }
+            _ => return Err(format!("Unknown setting `{}`. Available: {}", key, KEYS)),
+        };
+
+        Ok(setting)
+    }
+
+    fn apply(self, settings: &mut GuildSettings) {
+        match self {
+            Setting::Prefix(value) => settings.prefix = value,
+            Setting::DjRole(value) => settings.dj_role = value,
+            Setting::Volume(value) => settings.default_volume = value,
+            Setting::AnnounceChannel(value) => settings.announce_channel = value,
+            Setting::IdleTimeout(value) => settings.idle_timeout = value,
+            Setting::MaxQueueLength(value) => settings.max_queue_length = value,
+            Setting::MaxPlaylistLength(value) => settings.max_playlist_length = value,
+            Setting::MaxTrackDuration(value) => settings.max_track_duration = value,
+            Setting::Autoplay(value) => settings.autoplay = value,
+            Setting::SearchProvider(value) => settings.search_provider = value,
+            Setting::Normalize(value) => settings.normalize = value,
+            Setting::Crossfade(value) => settings.crossfade = value,
+        }
+    }
+}
+
+fn parse_prefix(value: &str) -> Result<String, String> {
+    if value.is_empty() || value.len() > 5 || value.contains(char::is_whitespace) {
+        return Err("The prefix must be 1 to 5 characters without spaces.".to_string());
+    }
+
+    Ok(value.to_string())
+}
+
+// Volume is given in percent, like the `volume` of a track is shown
+fn parse_volume(value: &str) -> Result<f32, String> {
+    match value.trim_end_matches('%').parse::<f32>() {
+        Ok(percent) if (0.0..=200.0).contains(&percent) => Ok(percent / 100.0),
+        _ => Err("The volume must be a percentage between 0 and 200.".to_string()),
+    }
+}
+
+// Only providers yt-dlp can search make sense as the default
+fn parse_search_provider(value: &str) -> Result<SearchProvider, String> {
+    match SearchProvider::from_prefix(value.trim_end_matches(':')) {
+        Some(provider) if provider.search_query("", 1).is_some() => Ok(provider),
+        _ => Err("`search_provider` must be `yt`, `ytm` or `sc`.".to_string()),
+    }
+}
+
+fn parse_crossfade(value: &str) -> Result<u64, String> {
+    if value == "off" {
+        return Ok(0);
+    }
+
+    match value.trim_end_matches('s').parse::<u64>() {
+        Ok(secs) if secs <= MAX_CROSSFADE => Ok(secs),
+        _ => Err(format!("`crossfade` must be `off` or 0 to {} seconds.", MAX_CROSSFADE)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` is not a whole number.", value))
+}
+
+fn parse_id<T>(
+    value: &str,
+    from_mention: fn(&str) -> Option<T>,
+    from_id: fn(u64) -> T,
+    what: &str,
+) -> Result<T, String> {
+    from_mention(value)
+        .or_else(|| value.parse::<u64>().ok().filter(|id| *id != 0).map(from_id))
+        .ok_or_else(|| format!("`{}` is not {} mention or ID.", value, what))
+}
+
+fn or_default(value: Option<String>, default: String) -> String {
+    value.unwrap_or_else(|| format!("{} *(default)*", default))
+}
+
+fn describe_provider(provider: SearchProvider) -> String {
+    format!("`{}` ({})", provider.prefix(), provider.name())
+}
+
+fn limit(value: usize) -> String {
+    match value {
+        0 => "unlimited".to_string(),
+        n => n.to_string(),
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(set_setting, reset_setting)]
+async fn settings(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+    let config = get_config(ctx).await;
+    let settings = get_guild_settings(ctx, guild_id).await;
+    let prefix = get_prefix(ctx, msg).await;
+
+    let fields = vec![
+        (
+            "prefix",
+            or_default(
+                settings.prefix.as_ref().map(|p| format!("`{}`", p)),
+                format!("`{}`", config.prefix),
+            ),
+            true,
+        ),
+        (
+            "dj_role",
+            or_default(settings.dj_role.map(|id| id.mention().to_string()), "none".to_string()),
+            true,
+        ),
+        (
+            "volume",
+            or_default(
+                settings.default_volume.map(|v| format!("{:.0}%", v * 100.0)),
+                format!("{:.0}%", config.default_volume * 100.0),
+            ),
+            true,
+        ),
+        (
+            "announce_channel",
+            or_default(
+                settings.announce_channel.map(|id| id.mention().to_string()),
+                "none".to_string(),
+            ),
+            true,
+        ),
+        (
+            "idle_timeout",
+            or_default(
+                settings.idle_timeout.map(|s| format!("{}s", s)),
+                format!("{}s", config.idle_timeout),
+            ),
+            true,
+        ),
+        (
+            "max_queue_length",
+            or_default(
+                settings.max_queue_length.map(limit),
+                limit(config.limits.max_queue_length),
+            ),
+            true,
+        ),
+        (
+            "max_playlist_length",
+            or_default(
+                settings.max_playlist_length.map(limit),
+                limit(config.limits.max_playlist_length),
+            ),
+            true,
+        ),
+        (
+            "max_track_duration",
+            or_default(
+                settings.max_track_duration.map(|s| limit(s as usize)),
+                limit(config.limits.max_track_duration as usize),
+            ),
+            true,
+        ),
+        (
+            "autoplay",
+            if settings.autoplay { "on" } else { "off" }.to_string(),
+            true,
+        ),
+        (
+            "search_provider",
+            or_default(
+                settings.search_provider.map(describe_provider),
+                describe_provider(SearchProvider::default()),
+            ),
+            true,
+        ),
+        (
+            "normalize",
+            if settings.normalize { "on" } else { "off" }.to_string(),
+            true,
+        ),
+        (
+            "crossfade",
+            match settings.crossfade {
+                0 => "off".to_string(),
+                secs => format!("{}s", secs),
+            },
+            true,
+        ),
+    ];
+
+    let embed = CreateEmbed::default()
+        .color(0xffffff)
+        .title(":gear: Server settings")
+        .description(format!(
+            "Change with `{0}settings set <key> <value>`, undo with `{0}settings reset <key>`.",
+            prefix
+        ))
+        .fields(fields)
+        .timestamp(Timestamp::now());
+    msg.channel_id
+        .send_message(&ctx.http, CreateMessage::default().add_embed(embed))
+        .await?;
+
+    Ok(())
+}
+
+#[command("set")]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_GUILD)]
+async fn set_setting(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let key = args.single::<String>().unwrap_or_default();
+    let value = args.rest().trim();
+    if key.is_empty() || value.is_empty() {
+        send_warning(ctx, msg, "Use the command like this: settings set <key> <value>").await?;
+        return Ok(());
+    }
+
+    change_setting(ctx, msg, &key, Some(value)).await
+}
+
+#[command("reset")]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_GUILD)]
+async fn reset_setting(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let Ok(key) = args.single::<String>() else {
+        send_warning(ctx, msg, "Use the command like this: settings reset <key>").await?;
+        return Ok(());
+    };
+
+    change_setting(ctx, msg, &key, None).await
+}
+
+async fn change_setting(
+    ctx: &Context,
+    msg: &Message,
+    key: &str,
+    value: Option<&str>,
+) -> CommandResult {
+    let guild_id = get_guild_id_from_message(msg, ctx)?;
+
+    let setting = match Setting::parse(key, value) {
+        Ok(setting) => setting,
+        Err(reason) => {
+            send_warning(ctx, msg, &reason).await?;
+            return Ok(());
+        }
+    };
+
+    let unknown = match &setting {
+        Setting::DjRole(Some(role_id)) => msg
+            .guild(&ctx.cache)
+            .is_some_and(|guild| !guild.roles.contains_key(role_id))
+            .then_some("That role does not exist in this server."),
+        Setting::AnnounceChannel(Some(channel_id)) => msg
+            .guild(&ctx.cache)
+            .is_some_and(|guild| !guild.channels.contains_key(channel_id))
+            .then_some("That channel does not exist in this server."),
+        _ => None,
+    };
+    if let Some(reason) = unknown {
+        send_warning(ctx, msg, reason).await?;
+        return Ok(());
+    }
+
+    let store = {
+        let data = ctx.data.read().await;
+        data.get::<GuildSettingsKey>()
+            .cloned()
+            .expect("Should exist in typemap")
+    };
+
+    let settings = match store.update(guild_id, |settings| setting.apply(settings)).await {
+        Ok(settings) => settings,
+        Err(e) => {
+            warn!("settings: Failed to save {} for guild {:?}: {}", key, guild_id, e);
+            send_error_message(ctx, msg, "Could not save the setting.").await?;
+            return Ok(());
+        }
+    };
+
+    // Playing tracks pick up the new normalization right away
+    get_filters(ctx, guild_id).await.set_normalize(settings.normalize);
+
+    info!(
+        "settings: {} changed {} to {:?} in guild {:?}",
+        msg.author.name, key, value, guild_id
+    );
+
+    let title = match value {
+        Some(value) => format!(":gear: `{}` set to {}", key, value),
+        None => format!(":gear: `{}` reset to the default", key),
+    };
+    send_success_message(ctx, msg, &title).await?;
+
+    Ok(())
+}
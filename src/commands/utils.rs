@@ -1,10 +1,24 @@
+use std::sync::Arc;
+
 use serenity::builder::{CreateEmbed, CreateMessage};
 use serenity::client::Context;
 use serenity::framework::standard::CommandResult;
 use serenity::model::channel::Message;
-use serenity::model::id::GuildId;
+use serenity::model::guild::Member;
+use serenity::model::id::{GuildId, RoleId};
 use serenity::model::Timestamp;
 
+use crate::cache::TrackCache;
+use crate::commands::music::crossfade::Crossfades;
+use crate::commands::music::resolver::ResolverRegistry;
+use crate::config::Config;
+use crate::filters::AudioFilters;
+use crate::settings::GuildSettings;
+use crate::ytdlp::YtDlp;
+use crate::{
+    ConfigKey, CrossfadesKey, FilterStoreKey, GuildSettingsKey, ResolverKey, TrackCacheKey, YtDlpKey,
+};
+
 pub fn to_time(secs: u64) -> String {
     let sec = (secs % 60) as u8;
     let min = ((secs / 60) % 60) as u8;
@@ -65,3 +79,100 @@ pub async fn send_error_message(ctx: &Context, msg: &Message, title: &str) -> Co
 
     Ok(())
 }
+
+pub async fn get_config(ctx: &Context) -> &'static Config {
+    let data = ctx.data.read().await;
+    data.get::<ConfigKey>()
+        .copied()
+        .expect("Should exist in typemap")
+}
+
+pub async fn get_guild_settings(ctx: &Context, guild_id: GuildId) -> GuildSettings {
+    let settings = {
+        let data = ctx.data.read().await;
+        data.get::<GuildSettingsKey>()
+            .cloned()
+            .expect("Should exist in typemap")
+    };
+
+    settings.get(guild_id).await
+}
+
+pub async fn get_filters(ctx: &Context, guild_id: GuildId) -> AudioFilters {
+    let filters = {
+        let data = ctx.data.read().await;
+        data.get::<FilterStoreKey>()
+            .cloned()
+            .expect("Should exist in typemap")
+    };
+
+    filters.get(guild_id).await
+}
+
+pub async fn get_crossfades(ctx: &Context) -> Crossfades {
+    let data = ctx.data.read().await;
+    data.get::<CrossfadesKey>()
+        .cloned()
+        .expect("Should exist in typemap")
+}
+
+pub async fn get_track_cache(ctx: &Context) -> TrackCache {
+    let data = ctx.data.read().await;
+    data.get::<TrackCacheKey>()
+        .cloned()
+        .expect("Should exist in typemap")
+}
+
+pub async fn get_resolvers(ctx: &Context) -> Arc<ResolverRegistry> {
+    let data = ctx.data.read().await;
+    data.get::<ResolverKey>()
+        .cloned()
+        .expect("Should exist in typemap")
+}
+
+pub async fn get_ytdlp(ctx: &Context) -> YtDlp {
+    let data = ctx.data.read().await;
+    data.get::<YtDlpKey>()
+        .cloned()
+        .expect("Should exist in typemap")
+}
+
+// The guild's own prefix, or the configured one in DMs and unset guilds
+pub async fn get_prefix(ctx: &Context, msg: &Message) -> String {
+    let config = get_config(ctx).await;
+
+    match msg.guild_id {
+        Some(guild_id) => get_guild_settings(ctx, guild_id)
+            .await
+            .prefix(config)
+            .to_string(),
+        None => config.prefix.clone(),
+    }
+}
+
+// Members with the DJ role and server managers may control playback
+pub async fn has_dj_access(ctx: &Context, msg: &Message, dj_role: RoleId) -> bool {
+    match msg.member(ctx).await {
+        Ok(member) if member.roles.contains(&dj_role) => true,
+        Ok(member) => member_can_manage_guild(ctx, msg, &member),
+        Err(_) => false,
+    }
+}
+
+pub async fn can_manage_guild(ctx: &Context, msg: &Message) -> bool {
+    match msg.member(ctx).await {
+        Ok(member) => member_can_manage_guild(ctx, msg, &member),
+        Err(_) => false,
+    }
+}
+
+fn member_can_manage_guild(ctx: &Context, msg: &Message, member: &Member) -> bool {
+    msg.guild(&ctx.cache)
+        .and_then(|guild| {
+            guild
+                .channels
+                .get(&msg.channel_id)
+                .map(|channel| guild.user_permissions_in(channel, member))
+        })
+        .is_some_and(|permissions| permissions.manage_guild())
+}
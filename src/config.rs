@@ -0,0 +1,291 @@
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use reqwest::Url;
+use serde::Deserialize;
+use tracing::Level;
+
+// Settings are read from a TOML file (`CONFIG_PATH`, default `config.toml`),
+// then overridden by environment variables. See `config.example.toml`.
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub token: String,
+    pub prefix: String,
+    pub status: String,
+    pub ytdlp_path: String,
+    // Passed to every yt-dlp run, e.g. `--proxy`
+    pub ytdlp_args: Vec<String>,
+    // Netscape cookies file, for age-restricted and members-only videos
+    pub ytdlp_cookies: Option<PathBuf>,
+    // yt-dlp's `-S` format sorting, e.g. `acodec:opus,abr`
+    pub ytdlp_format_sort: Option<String>,
+    // yt-dlp processes allowed to run at once, across all guilds
+    pub ytdlp_workers: usize,
+    // Seconds a single yt-dlp lookup may take
+    pub ytdlp_timeout: u64,
+    // 1.0 is the source's own loudness
+    pub default_volume: f32,
+    // Seconds to stay in voice after the queue ends, 0 leaves right away
+    pub idle_timeout: u64,
+    pub log_level: String,
+    pub snapshot_path: PathBuf,
+    pub database_path: PathBuf,
+    pub shutdown_notice: Option<String>,
+    // Service asked for the tracks behind Spotify, Apple Music and Deezer
+    // links as `?url=<link>`, instead of reading their pages
+    pub link_metadata_url: Option<String>,
+    // Folder of audio files members may play with `local:`, unset to disable
+    pub library_path: Option<PathBuf>,
+    pub cache_path: PathBuf,
+    // Megabytes of downloaded tracks to keep, 0 disables the cache
+    pub cache_size: u64,
+    pub limits: Limits,
+}
+
+// A limit of 0 means unlimited
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_queue_length: usize,
+    pub max_playlist_length: usize,
+    // Seconds
+    pub max_track_duration: u64,
+    // Megabytes, for audio files played from attachments
+    pub max_attachment_size: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            prefix: "~".to_string(),
+            status: "music".to_string(),
+            ytdlp_path: "yt-dlp".to_string(),
+            ytdlp_args: Vec::new(),
+            ytdlp_cookies: None,
+            ytdlp_format_sort: None,
+            ytdlp_workers: 4,
+            ytdlp_timeout: 30,
+            default_volume: 1.0,
+            idle_timeout: 0,
+            log_level: "info".to_string(),
+            snapshot_path: PathBuf::from("data/queues.json"),
+            database_path: PathBuf::from("data/rmusicbot.db"),
+            shutdown_notice: None,
+            link_metadata_url: None,
+            library_path: None,
+            cache_path: PathBuf::from("data/cache"),
+            cache_size: 0,
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_queue_length: 1000,
+            max_playlist_length: 500,
+            max_track_duration: 0,
+            max_attachment_size: 25,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env { var: &'static str, value: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => {
+                write!(f, "could not read config file {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, e) => {
+                write!(f, "could not parse config file {}: {}", path.display(), e)
+            }
+            ConfigError::Env { var, value } => {
+                write!(f, "environment variable {} has an invalid value: {:?}", var, value)
+            }
+            ConfigError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match env::var("CONFIG_PATH") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            Err(_) => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env(&mut self.token, "DISCORD_TOKEN")?;
+        override_from_env(&mut self.prefix, "PREFIX")?;
+        override_from_env(&mut self.status, "DISCORD_STATUS")?;
+        override_from_env(&mut self.ytdlp_path, "YTDLP_PATH")?;
+        override_from_env(&mut self.ytdlp_workers, "YTDLP_WORKERS")?;
+        override_from_env(&mut self.ytdlp_timeout, "YTDLP_TIMEOUT")?;
+        override_from_env(&mut self.default_volume, "DEFAULT_VOLUME")?;
+        override_from_env(&mut self.idle_timeout, "IDLE_TIMEOUT")?;
+        override_from_env(&mut self.log_level, "LOG_LEVEL")?;
+        override_from_env(&mut self.snapshot_path, "SNAPSHOT_PATH")?;
+        override_from_env(&mut self.database_path, "DATABASE_PATH")?;
+        override_from_env(&mut self.cache_path, "CACHE_PATH")?;
+        override_from_env(&mut self.cache_size, "CACHE_SIZE")?;
+        override_from_env(&mut self.limits.max_queue_length, "MAX_QUEUE_LENGTH")?;
+        override_from_env(&mut self.limits.max_playlist_length, "MAX_PLAYLIST_LENGTH")?;
+        override_from_env(&mut self.limits.max_track_duration, "MAX_TRACK_DURATION")?;
+        override_from_env(&mut self.limits.max_attachment_size, "MAX_ATTACHMENT_SIZE")?;
+
+        if let Ok(notice) = env::var("SHUTDOWN_NOTICE") {
+            self.shutdown_notice = Some(notice).filter(|notice| !notice.is_empty());
+        }
+
+        // Split like a shell would, so quoted arguments may contain spaces
+        if let Ok(args) = env::var("YTDLP_ARGS") {
+            self.ytdlp_args = shell_words::split(&args).map_err(|_| ConfigError::Env {
+                var: "YTDLP_ARGS",
+                value: args.clone(),
+            })?;
+        }
+
+        if let Ok(path) = env::var("YTDLP_COOKIES") {
+            self.ytdlp_cookies = Some(PathBuf::from(path)).filter(|path| !path.as_os_str().is_empty());
+        }
+
+        if let Ok(sort) = env::var("YTDLP_FORMAT_SORT") {
+            self.ytdlp_format_sort = Some(sort).filter(|sort| !sort.is_empty());
+        }
+
+        if let Ok(url) = env::var("LINK_METADATA_URL") {
+            self.link_metadata_url = Some(url).filter(|url| !url.is_empty());
+        }
+
+        if let Ok(path) = env::var("LIBRARY_PATH") {
+            self.library_path = Some(PathBuf::from(path)).filter(|path| !path.as_os_str().is_empty());
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.token.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "no Discord token configured: set `token` in the config file or DISCORD_TOKEN"
+                    .to_string(),
+            ));
+        }
+
+        if self.prefix.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "`prefix` / PREFIX must not be empty".to_string(),
+            ));
+        }
+
+        if self.ytdlp_workers == 0 || self.ytdlp_timeout == 0 {
+            return Err(ConfigError::Invalid(
+                "`ytdlp_workers` / YTDLP_WORKERS and `ytdlp_timeout` / YTDLP_TIMEOUT must be above 0"
+                    .to_string(),
+            ));
+        }
+
+        if !(0.0..=2.0).contains(&self.default_volume) {
+            return Err(ConfigError::Invalid(format!(
+                "`default_volume` / DEFAULT_VOLUME must be between 0.0 and 2.0, got {}",
+                self.default_volume
+            )));
+        }
+
+        if Level::from_str(&self.log_level).is_err() {
+            return Err(ConfigError::Invalid(format!(
+                "`log_level` / LOG_LEVEL must be one of trace, debug, info, warn, error, got {:?}",
+                self.log_level
+            )));
+        }
+
+        if let Some(path) = &self.ytdlp_cookies {
+            if !path.is_file() {
+                return Err(ConfigError::Invalid(format!(
+                    "`ytdlp_cookies` / YTDLP_COOKIES must be a file, got {}",
+                    path.display()
+                )));
+            }
+        }
+
+        if let Some(url) = &self.link_metadata_url {
+            if !Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+                return Err(ConfigError::Invalid(format!(
+                    "`link_metadata_url` / LINK_METADATA_URL must be an http(s) URL, got {:?}",
+                    url
+                )));
+            }
+        }
+
+        if let Some(path) = &self.library_path {
+            if !path.is_dir() {
+                return Err(ConfigError::Invalid(format!(
+                    "`library_path` / LIBRARY_PATH must be a directory, got {}",
+                    path.display()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    // The options every yt-dlp run gets ahead of its own. The cookies file is
+    // handed over separately, as a copy per run (see `CookieCopy`).
+    pub fn ytdlp_args(&self) -> Vec<String> {
+        let mut args = self.ytdlp_args.clone();
+        if let Some(sort) = &self.ytdlp_format_sort {
+            args.push("-S".to_string());
+            args.push(sort.clone());
+        }
+
+        args
+    }
+
+    pub fn log_level(&self) -> Level {
+        Level::from_str(&self.log_level).unwrap_or(Level::INFO)
+    }
+}
+
+fn override_from_env<T: FromStr>(field: &mut T, var: &'static str) -> Result<(), ConfigError> {
+    if let Ok(value) = env::var(var) {
+        *field = value
+            .parse()
+            .map_err(|_| ConfigError::Env { var, value })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,754 @@
+use std::f32::consts::{FRAC_1_SQRT_2, PI, SQRT_2};
+
+use super::{FilterPreset, FilterSettings};
+
+// The processing behind each filter, on interleaved stereo frames of `f32`
+// samples at the track's own sample rate.
+
+pub type Frame = [f32; 2];
+
+const BASS_BOOST_FREQ: f32 = 100.0;
+// dB
+const BASS_BOOST_GAIN: f32 = 8.0;
+// How wide each custom equalizer band is
+const EQ_Q: f32 = 1.0;
+// Below this, centred sound is kept by karaoke so the bass and kick stay
+const KARAOKE_CUTOFF: f32 = 200.0;
+// Seconds for one turn around the listener
+const EIGHT_D_PERIOD: f32 = 10.0;
+// Seconds of audio in each piece the time stretch overlaps
+const STRETCH_WINDOW: f32 = 0.04;
+// Seconds a piece may move to line up with the one before it
+const STRETCH_TOLERANCE: f32 = 0.01;
+// Only every few frames are compared when lining pieces up
+const STRETCH_COMPARE_STEP: usize = 4;
+// LUFS, what the big streaming services play at
+const TARGET_LOUDNESS: f64 = -14.0;
+// dB the normalizer may turn a track up or down by at most
+const MAX_BOOST: f64 = 12.0;
+const MAX_CUT: f64 = 24.0;
+// Seconds for the gain to get most of the way to a new value
+const GAIN_SMOOTHING: f32 = 1.0;
+// Just under full scale, so boosted peaks don't clip
+const LIMITER_CEILING: f32 = 0.95;
+// Seconds for the limiter to let go after a peak
+const LIMITER_RELEASE: f32 = 0.05;
+// Quieter blocks than this (LUFS) are silence and aren't measured
+const ABSOLUTE_GATE: f64 = -70.0;
+// The loudness measurements kept, in 0.1 LU steps above the gate
+const LOUDNESS_BINS: usize = 800;
+
+// Biquad coefficients, from the Audio EQ Cookbook
+#[derive(Clone, Copy)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    fn normalized(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    // Frequencies above Nyquist would make the filter unstable
+    fn omega(rate: u32, freq: f32) -> f32 {
+        2.0 * PI * freq.min(rate as f32 * 0.45) / rate as f32
+    }
+
+    fn peaking(rate: u32, freq: f32, gain: f32, q: f32) -> Self {
+        let a = 10f32.powf(gain / 40.0);
+        let w0 = Self::omega(rate, freq);
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+
+        Self::normalized(
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        )
+    }
+
+    fn low_shelf(rate: u32, freq: f32, gain: f32) -> Self {
+        let a = 10f32.powf(gain / 40.0);
+        let w0 = Self::omega(rate, freq);
+        let cos = w0.cos();
+        // Shelf slope of 1
+        let beta = a.sqrt() * w0.sin() * SQRT_2;
+
+        Self::normalized(
+            a * ((a + 1.0) - (a - 1.0) * cos + beta),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - beta),
+            (a + 1.0) + (a - 1.0) * cos + beta,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - beta,
+        )
+    }
+
+    fn high_shelf(rate: u32, freq: f32, gain: f32) -> Self {
+        let a = 10f32.powf(gain / 40.0);
+        let w0 = Self::omega(rate, freq);
+        let cos = w0.cos();
+        let beta = a.sqrt() * w0.sin() * SQRT_2;
+
+        Self::normalized(
+            a * ((a + 1.0) + (a - 1.0) * cos + beta),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - beta),
+            (a + 1.0) - (a - 1.0) * cos + beta,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - beta,
+        )
+    }
+
+    fn high_pass(rate: u32, freq: f32, q: f32) -> Self {
+        let w0 = Self::omega(rate, freq);
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+
+        Self::normalized(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    fn low_pass(rate: u32, freq: f32) -> Self {
+        let w0 = Self::omega(rate, freq);
+        let alpha = w0.sin() / (2.0 * FRAC_1_SQRT_2);
+        let cos = w0.cos();
+
+        Self::normalized(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+}
+
+// One channel's filter memory
+#[derive(Clone, Copy, Default)]
+struct BiquadState {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl BiquadState {
+    fn process(&mut self, c: &Coefficients, x: f32) -> f32 {
+        let y = c.b0 * x + c.b1 * self.x1 + c.b2 * self.x2 - c.a1 * self.y1 - c.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+
+        y
+    }
+}
+
+trait Stage: Send + Sync {
+    fn process(&mut self, frames: &mut [Frame]);
+}
+
+struct Biquad {
+    coefficients: Coefficients,
+    channels: [BiquadState; 2],
+}
+
+impl Biquad {
+    fn new(coefficients: Coefficients) -> Self {
+        Self {
+            coefficients,
+            channels: [BiquadState::default(); 2],
+        }
+    }
+}
+
+impl Stage for Biquad {
+    fn process(&mut self, frames: &mut [Frame]) {
+        for frame in frames {
+            for (sample, state) in frame.iter_mut().zip(&mut self.channels) {
+                *sample = state.process(&self.coefficients, *sample);
+            }
+        }
+    }
+}
+
+// Cancels what both channels share, except for the low end
+struct Karaoke {
+    coefficients: Coefficients,
+    low: BiquadState,
+}
+
+impl Stage for Karaoke {
+    fn process(&mut self, frames: &mut [Frame]) {
+        for frame in frames {
+            let [left, right] = *frame;
+            let side = (left - right) / 2.0;
+            let low = self.low.process(&self.coefficients, (left + right) / 2.0);
+            *frame = [low + side, low - side];
+        }
+    }
+}
+
+// Pans the mono mix around, at equal power so it doesn't dip in the middle
+struct Rotation {
+    phase: f32,
+    step: f32,
+}
+
+impl Stage for Rotation {
+    fn process(&mut self, frames: &mut [Frame]) {
+        for frame in frames {
+            let mono = (frame[0] + frame[1]) / 2.0;
+            // 0 is hard left, PI / 2 hard right
+            let angle = (self.phase.sin() + 1.0) * PI / 4.0;
+            *frame = [
+                mono * angle.cos() * SQRT_2,
+                mono * angle.sin() * SQRT_2,
+            ];
+            self.phase = (self.phase + self.step) % (2.0 * PI);
+        }
+    }
+}
+
+// Plays the input faster or slower by linear interpolation, which moves the
+// pitch along with the speed
+struct RateChanger {
+    step: f64,
+    // Position in the current input, counted from the frame before it
+    pos: f64,
+    prev: Frame,
+}
+
+impl RateChanger {
+    fn new(rate: f64) -> Self {
+        Self {
+            step: rate,
+            pos: 0.0,
+            prev: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: &[Frame], output: &mut Vec<Frame>) {
+        let Some(last) = input.last() else {
+            return;
+        };
+
+        let len = input.len() as f64;
+        while self.pos < len {
+            let idx = self.pos as usize;
+            let frac = (self.pos - idx as f64) as f32;
+            let a = if idx == 0 { self.prev } else { input[idx - 1] };
+            let b = input[idx];
+            output.push([a[0] + (b[0] - a[0]) * frac, a[1] + (b[1] - a[1]) * frac]);
+            self.pos += self.step;
+        }
+
+        self.pos -= len;
+        self.prev = *last;
+    }
+}
+
+// Changes the tempo but not the pitch (WSOLA): overlapping pieces of the
+// input are added back together closer or further apart, each one moved a
+// little so its waveform lines up with where the last one left off.
+struct TimeStretch {
+    tempo: f64,
+    // Hann window over two hops, so overlapping halves add up to one
+    window: Vec<f32>,
+    hop: usize,
+    tolerance: usize,
+    input: Vec<Frame>,
+    // Where the next piece would start without lining up, in `input`
+    nominal: f64,
+    // Where the last piece would have carried on, in `input`
+    natural: Option<usize>,
+    // Second half of the last piece, still to be overlapped
+    tail: Vec<Frame>,
+}
+
+impl TimeStretch {
+    fn new(tempo: f64, sample_rate: u32) -> Self {
+        let hop = ((sample_rate as f32 * STRETCH_WINDOW) as usize / 2).max(1);
+        let window = (0..hop * 2)
+            .map(|n| 0.5 - 0.5 * (PI * n as f32 / hop as f32).cos())
+            .collect();
+
+        Self {
+            tempo,
+            window,
+            hop,
+            tolerance: (sample_rate as f32 * STRETCH_TOLERANCE) as usize,
+            input: Vec::new(),
+            nominal: 0.0,
+            natural: None,
+            tail: vec![[0.0; 2]; hop],
+        }
+    }
+
+    // The start near `nominal` whose first half sounds most like what
+    // follows the last piece
+    fn best_start(&self, nominal: usize, natural: usize) -> usize {
+        let mono = |frame: &Frame| frame[0] + frame[1];
+        let target = &self.input[natural..natural + self.hop];
+        let mut best = (nominal, f32::MIN);
+
+        for start in nominal.saturating_sub(self.tolerance)..=nominal + self.tolerance {
+            let candidate = &self.input[start..start + self.hop];
+            let (mut correlation, mut energy) = (0.0, 0.0);
+            for (a, b) in candidate.iter().zip(target).step_by(STRETCH_COMPARE_STEP) {
+                correlation += mono(a) * mono(b);
+                energy += mono(a) * mono(a);
+            }
+            let score = correlation / (energy + 1e-9).sqrt();
+            if score > best.1 {
+                best = (start, score);
+            }
+        }
+
+        best.0
+    }
+
+    fn process(&mut self, input: &[Frame], output: &mut Vec<Frame>) {
+        self.input.extend_from_slice(input);
+
+        loop {
+            let nominal = self.nominal.round() as usize;
+            if self.input.len() < nominal + self.tolerance + self.hop * 2 {
+                break;
+            }
+
+            let start = match self.natural {
+                Some(natural) => self.best_start(nominal, natural),
+                None => nominal,
+            };
+            let piece = &self.input[start..start + self.hop * 2];
+            let (rising, falling) = self.window.split_at(self.hop);
+
+            for ((tail, frame), weight) in self.tail.iter().zip(piece).zip(rising) {
+                output.push([tail[0] + frame[0] * weight, tail[1] + frame[1] * weight]);
+            }
+            for ((tail, frame), weight) in self.tail.iter_mut().zip(&piece[self.hop..]).zip(falling) {
+                *tail = [frame[0] * weight, frame[1] * weight];
+            }
+
+            self.natural = Some(start + self.hop);
+            self.nominal += self.hop as f64 * self.tempo;
+        }
+
+        // Drop what no piece can start at anymore
+        let nominal = self.nominal.round() as usize;
+        let used = nominal
+            .saturating_sub(self.tolerance)
+            .min(self.natural.unwrap_or(nominal))
+            .min(self.input.len());
+        self.input.drain(..used);
+        self.nominal -= used as f64;
+        self.natural = self.natural.map(|natural| natural - used);
+    }
+}
+
+fn loudness(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+// Brings tracks to the same loudness, measured the EBU R128 way: K-weighted
+// 400 ms blocks, gated to leave out silence and quiet passages. The track's
+// loudness so far sets the gain, which follows it slowly, and a limiter
+// catches the peaks a boost pushes over full scale.
+struct Normalizer {
+    // K-weighting, per channel
+    shelf: Coefficients,
+    high_pass: Coefficients,
+    weighting: [[BiquadState; 2]; 2],
+    // Frames in 100 ms, four of which make a block
+    step_len: usize,
+    step_energy: f64,
+    step_frames: usize,
+    steps: [f64; 4],
+    steps_seen: usize,
+    // Number and summed mean square of the blocks measured at each loudness
+    histogram: Vec<(u32, f64)>,
+    gain: f32,
+    target_gain: f32,
+    smoothing: f32,
+    limiter: f32,
+    release: f32,
+}
+
+impl Normalizer {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f32;
+
+        Self {
+            shelf: Coefficients::high_shelf(sample_rate, 1681.97, 4.0),
+            high_pass: Coefficients::high_pass(sample_rate, 38.13, 0.5),
+            weighting: [[BiquadState::default(); 2]; 2],
+            step_len: (sample_rate / 10).max(1) as usize,
+            step_energy: 0.0,
+            step_frames: 0,
+            steps: [0.0; 4],
+            steps_seen: 0,
+            histogram: vec![(0, 0.0); LOUDNESS_BINS],
+            gain: 1.0,
+            target_gain: 1.0,
+            smoothing: 1.0 - (-1.0 / (GAIN_SMOOTHING * rate)).exp(),
+            limiter: 1.0,
+            release: 1.0 - (-1.0 / (LIMITER_RELEASE * rate)).exp(),
+        }
+    }
+
+    // The gated loudness of everything measured, once there is any
+    fn integrated(&self) -> Option<f64> {
+        let (count, energy) = self
+            .histogram
+            .iter()
+            .fold((0, 0.0), |(count, energy), bin| (count + bin.0, energy + bin.1));
+        if count == 0 {
+            return None;
+        }
+
+        // Blocks more than 10 LU under the average don't count
+        let gate = loudness(energy / count as f64) - 10.0;
+        let first = (((gate - ABSOLUTE_GATE) * 10.0).max(0.0) as usize).min(LOUDNESS_BINS);
+        let (count, energy) = self.histogram[first..]
+            .iter()
+            .fold((0, 0.0), |(count, energy), bin| (count + bin.0, energy + bin.1));
+
+        (count > 0).then(|| loudness(energy / count as f64))
+    }
+
+    fn measure(&mut self, frame: Frame) {
+        for (channel, sample) in frame.into_iter().enumerate() {
+            let [shelf, high_pass] = &mut self.weighting[channel];
+            let weighted = high_pass.process(&self.high_pass, shelf.process(&self.shelf, sample));
+            self.step_energy += (weighted * weighted) as f64;
+        }
+
+        self.step_frames += 1;
+        if self.step_frames < self.step_len {
+            return;
+        }
+
+        self.steps[self.steps_seen % 4] = self.step_energy / self.step_len as f64;
+        self.steps_seen += 1;
+        self.step_energy = 0.0;
+        self.step_frames = 0;
+        if self.steps_seen < 4 {
+            return;
+        }
+
+        let block = self.steps.iter().sum::<f64>() / 4.0;
+        let block_loudness = loudness(block);
+        if block_loudness <= ABSOLUTE_GATE {
+            return;
+        }
+        let bin = (((block_loudness - ABSOLUTE_GATE) * 10.0) as usize).min(LOUDNESS_BINS - 1);
+        self.histogram[bin].0 += 1;
+        self.histogram[bin].1 += block;
+
+        if let Some(integrated) = self.integrated() {
+            let gain = (TARGET_LOUDNESS - integrated).clamp(-MAX_CUT, MAX_BOOST);
+            self.target_gain = 10f64.powf(gain / 20.0) as f32;
+        }
+    }
+}
+
+impl Stage for Normalizer {
+    fn process(&mut self, frames: &mut [Frame]) {
+        for frame in frames {
+            self.measure(*frame);
+            self.gain += (self.target_gain - self.gain) * self.smoothing;
+
+            // Released first, so the frame that would go over is caught too
+            let peak = frame[0].abs().max(frame[1].abs()) * self.gain;
+            self.limiter += (1.0 - self.limiter) * self.release;
+            if peak * self.limiter > LIMITER_CEILING {
+                self.limiter = LIMITER_CEILING / peak;
+            }
+
+            let gain = self.gain * self.limiter;
+            *frame = [frame[0] * gain, frame[1] * gain];
+        }
+    }
+}
+
+// Everything the settings ask for, in a fixed order: tempo and speed first,
+// then the effects on the sound, then where it is heard, and the loudness of
+// the result last
+pub struct FilterChain {
+    sample_rate: u32,
+    stretch: Option<TimeStretch>,
+    stretched: Vec<Frame>,
+    rate: Option<RateChanger>,
+    stages: Vec<Box<dyn Stage>>,
+    normalizer: Option<Normalizer>,
+}
+
+impl FilterChain {
+    pub fn new(settings: &FilterSettings, sample_rate: u32) -> Self {
+        let rate = settings.rate();
+        let tempo = settings.tempo();
+        let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+
+        if settings.has(FilterPreset::Karaoke) {
+            stages.push(Box::new(Karaoke {
+                coefficients: Coefficients::low_pass(sample_rate, KARAOKE_CUTOFF),
+                low: BiquadState::default(),
+            }));
+        }
+        if settings.has(FilterPreset::BassBoost) {
+            stages.push(Box::new(Biquad::new(Coefficients::low_shelf(
+                sample_rate,
+                BASS_BOOST_FREQ,
+                BASS_BOOST_GAIN,
+            ))));
+        }
+        for band in &settings.equalizer {
+            stages.push(Box::new(Biquad::new(Coefficients::peaking(
+                sample_rate,
+                band.freq,
+                band.gain,
+                EQ_Q,
+            ))));
+        }
+        if settings.has(FilterPreset::EightD) {
+            stages.push(Box::new(Rotation {
+                phase: 0.0,
+                step: 2.0 * PI / (EIGHT_D_PERIOD * sample_rate as f32),
+            }));
+        }
+
+        Self {
+            sample_rate,
+            stretch: (tempo != 1.0).then(|| TimeStretch::new(tempo, sample_rate)),
+            stretched: Vec::new(),
+            rate: (rate != 1.0).then(|| RateChanger::new(rate)),
+            stages,
+            normalizer: settings.normalize.then(|| Normalizer::new(sample_rate)),
+        }
+    }
+
+    // A chain for new settings, still knowing how loud the track has been
+    // so the gain doesn't start over
+    pub fn rebuild(&mut self, settings: &FilterSettings) {
+        let normalizer = self.normalizer.take();
+        *self = Self::new(settings, self.sample_rate);
+        if self.normalizer.is_some() {
+            self.normalizer = normalizer.or(self.normalizer.take());
+        }
+    }
+
+    pub fn process(&mut self, input: &[Frame], output: &mut Vec<Frame>) {
+        output.clear();
+        let input = match &mut self.stretch {
+            Some(stretch) => {
+                self.stretched.clear();
+                stretch.process(input, &mut self.stretched);
+                &self.stretched
+            }
+            None => input,
+        };
+        match &mut self.rate {
+            Some(rate) => rate.process(input, output),
+            None => output.extend_from_slice(input),
+        }
+
+        if self.stages.is_empty() && self.normalizer.is_none() {
+            return;
+        }
+        for stage in &mut self.stages {
+            stage.process(output);
+        }
+        if let Some(normalizer) = &mut self.normalizer {
+            normalizer.process(output);
+        }
+        // Boosts can push past full scale, which would wrap around when encoded
+        for frame in output.iter_mut() {
+            for sample in frame {
+                *sample = sample.clamp(-1.0, 1.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::EqBand;
+
+    fn sine(freq: f32, amplitude: f32, rate: u32, frames: usize) -> Vec<Frame> {
+        (0..frames)
+            .map(|n| {
+                let sample = (2.0 * PI * freq * n as f32 / rate as f32).sin() * amplitude;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn peak(frames: &[Frame]) -> f32 {
+        frames
+            .iter()
+            .flat_map(|frame| frame.iter())
+            .fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn flat_equalizer_passes_audio_through() {
+        let settings = FilterSettings {
+            equalizer: vec![
+                EqBand { freq: 60.0, gain: 0.0 },
+                EqBand { freq: 1000.0, gain: 0.0 },
+                EqBand { freq: 12000.0, gain: 0.0 },
+            ],
+            ..Default::default()
+        };
+        let input = sine(440.0, 0.5, 48000, 4800);
+        let mut output = Vec::new();
+
+        FilterChain::new(&settings, 48000).process(&input, &mut output);
+        assert_eq!(output.len(), input.len());
+        // Only rounding is left, far below anything audible
+        let difference: Vec<Frame> = output
+            .iter()
+            .zip(&input)
+            .map(|(out, sample)| [out[0] - sample[0], out[1] - sample[1]])
+            .collect();
+        assert!(peak(&difference) < 1e-3);
+    }
+
+    #[test]
+    fn bass_boost_raises_lows_only() {
+        let settings = FilterSettings {
+            presets: vec![FilterPreset::BassBoost],
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+
+        FilterChain::new(&settings, 48000).process(&sine(50.0, 0.5, 48000, 48000), &mut output);
+        assert!(peak(&output[24000..]) > 0.5 * 1.8);
+
+        FilterChain::new(&settings, 48000).process(&sine(5000.0, 0.5, 48000, 48000), &mut output);
+        assert!((peak(&output[24000..]) - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn karaoke_removes_the_centre() {
+        let settings = FilterSettings {
+            presets: vec![FilterPreset::Karaoke],
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+
+        FilterChain::new(&settings, 48000).process(&sine(1000.0, 0.5, 48000, 48000), &mut output);
+        assert!(peak(&output[24000..]) < 0.05);
+    }
+
+    // Both are fed in packets, as the decoder hands them over
+    fn feed(input: &[Frame], mut process: impl FnMut(&[Frame], &mut Vec<Frame>)) -> Vec<Frame> {
+        let mut output = Vec::new();
+        for packet in input.chunks(960) {
+            process(packet, &mut output);
+        }
+        output
+    }
+
+    #[test]
+    fn rate_changer_length_follows_the_rate() {
+        let input = sine(440.0, 0.5, 48000, 48000);
+
+        for rate in [0.5, 0.8, 1.25, 2.0] {
+            let mut changer = RateChanger::new(rate);
+            let output = feed(&input, |packet, output| changer.process(packet, output));
+            let expected = (input.len() as f64 / rate).round() as usize;
+            assert!(output.len().abs_diff(expected) <= 1, "{} frames at {}", output.len(), rate);
+        }
+    }
+
+    #[test]
+    fn time_stretch_length_follows_the_tempo() {
+        let input = sine(440.0, 0.5, 48000, 48000 * 4);
+
+        for tempo in [0.5, 0.8, 1.25, 2.0] {
+            let mut stretch = TimeStretch::new(tempo, 48000);
+            let output = feed(&input, |packet, output| stretch.process(packet, output));
+            // What is held back for the next piece is at most two hops and
+            // the search around it
+            let expected = input.len() as f64 / tempo;
+            let held_back = (stretch.hop * 2 + stretch.tolerance) as f64 / tempo;
+            let missing = expected - output.len() as f64;
+            assert!(
+                (0.0..=held_back + stretch.hop as f64).contains(&missing),
+                "{} frames at {}, expected about {}",
+                output.len(),
+                tempo,
+                expected
+            );
+        }
+    }
+
+    // Integrated loudness of `frames` in LUFS, by a normalizer that only listens
+    fn measured_loudness(frames: &[Frame]) -> f64 {
+        let mut meter = Normalizer::new(48000);
+        for frame in frames {
+            meter.measure(*frame);
+        }
+        meter.integrated().expect("loud enough to measure")
+    }
+
+    #[test]
+    fn normalizer_reaches_target_loudness() {
+        for amplitude in [0.1, 0.3, 0.9] {
+            let input = sine(997.0, amplitude, 48000, 48000 * 20);
+            let mut output = input.clone();
+            let mut normalizer = Normalizer::new(48000);
+            for packet in output.chunks_mut(960) {
+                normalizer.process(packet);
+            }
+
+            // Once the gain has settled
+            let loudness = measured_loudness(&output[48000 * 10..]);
+            assert!(
+                (loudness - TARGET_LOUDNESS).abs() < 0.5,
+                "{:.2} LUFS from {} at {:.2} LUFS",
+                loudness,
+                amplitude,
+                measured_loudness(&input)
+            );
+        }
+    }
+
+    #[test]
+    fn limiter_keeps_peaks_under_the_ceiling() {
+        // A quiet intro gets boosted, then the full scale part arrives
+        // before the gain can come down
+        let mut input = sine(997.0, 0.02, 48000, 48000 * 8);
+        input.extend(sine(80.0, 1.0, 48000, 48000 * 2));
+        let mut normalizer = Normalizer::new(48000);
+
+        for packet in input.chunks_mut(960) {
+            normalizer.process(packet);
+            assert!(peak(packet) <= LIMITER_CEILING + 1e-6, "peak {}", peak(packet));
+        }
+    }
+}
@@ -0,0 +1,316 @@
+mod dsp;
+mod source;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock as StdRwLock};
+use std::time::Duration;
+
+use serenity::model::id::GuildId;
+use serenity::prelude::*;
+use songbird::input::{Input, LiveInput};
+use tracing::debug;
+
+use self::source::{FilterSource, FilteredCompose};
+
+// Audio effects, applied by decoding each track ourselves and running the
+// samples through `dsp` before songbird gets them. Every queued track reads
+// its guild's settings as it plays, so a change is heard right away on the
+// current track, from where it is, and on everything after it.
+
+// Custom equalizer bands allowed at once
+pub const MAX_EQ_BANDS: usize = 10;
+pub const MIN_SPEED: f64 = 0.5;
+pub const MAX_SPEED: f64 = 2.0;
+// Semitones either way
+pub const MAX_PITCH: f64 = 12.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterPreset {
+    BassBoost,
+    // Faster and higher
+    Nightcore,
+    // Slower and lower
+    Vaporwave,
+    // The sound circling around the listener
+    EightD,
+    // Removes what is mixed to the centre, usually the vocals
+    Karaoke,
+}
+
+pub const PRESETS: &[FilterPreset] = &[
+    FilterPreset::BassBoost,
+    FilterPreset::Nightcore,
+    FilterPreset::Vaporwave,
+    FilterPreset::EightD,
+    FilterPreset::Karaoke,
+];
+
+impl FilterPreset {
+    pub fn key(self) -> &'static str {
+        match self {
+            FilterPreset::BassBoost => "bassboost",
+            FilterPreset::Nightcore => "nightcore",
+            FilterPreset::Vaporwave => "vaporwave",
+            FilterPreset::EightD => "8d",
+            FilterPreset::Karaoke => "karaoke",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterPreset::BassBoost => "Bass boost",
+            FilterPreset::Nightcore => "Nightcore",
+            FilterPreset::Vaporwave => "Vaporwave",
+            FilterPreset::EightD => "8D",
+            FilterPreset::Karaoke => "Karaoke",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .copied()
+            .find(|preset| preset.key().eq_ignore_ascii_case(key))
+    }
+
+    // How much faster the track plays, pitch going along with it
+    fn rate(self) -> Option<f64> {
+        match self {
+            FilterPreset::Nightcore => Some(1.25),
+            FilterPreset::Vaporwave => Some(0.8),
+            _ => None,
+        }
+    }
+}
+
+// A peaking band of the custom equalizer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqBand {
+    // Hz
+    pub freq: f32,
+    // dB
+    pub gain: f32,
+}
+
+impl EqBand {
+    // `<hz>:<db>`, e.g. `60:+6` or `4000:-3`
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("`{}` is not a band, write them as `<hz>:<db>`, e.g. `60:+6`.", text);
+        let (freq, gain) = text.split_once(':').ok_or_else(invalid)?;
+        let freq: f32 = freq.trim_end_matches("hz").parse().map_err(|_| invalid())?;
+        let gain: f32 = gain.trim_end_matches("db").parse().map_err(|_| invalid())?;
+
+        if !(20.0..=20000.0).contains(&freq) {
+            return Err("Band frequencies must be between 20 and 20000 Hz.".to_string());
+        }
+        if !(-12.0..=12.0).contains(&gain) {
+            return Err("Band gains must be between -12 and +12 dB.".to_string());
+        }
+
+        Ok(Self { freq, gain })
+    }
+}
+
+impl fmt::Display for EqBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz {:+} dB", self.freq, self.gain)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FilterSettings {
+    // In the order they were turned on
+    pub presets: Vec<FilterPreset>,
+    pub equalizer: Vec<EqBand>,
+    // Tempo, keeping the pitch. `None` is normal speed.
+    pub speed: Option<f64>,
+    // Semitones, keeping the tempo
+    pub pitch: Option<f64>,
+    // Loudness normalization, from the guild's `normalize` setting rather
+    // than a filter of its own
+    pub normalize: bool,
+}
+
+impl FilterSettings {
+    pub fn is_empty(&self) -> bool {
+        self.presets.is_empty() && self.equalizer.is_empty() && self.speed.is_none() && self.pitch.is_none()
+    }
+
+    // Turns every filter off, leaving normalization as the guild set it
+    pub fn clear(&mut self) {
+        *self = Self {
+            normalize: self.normalize,
+            ..Self::default()
+        };
+    }
+
+    pub fn has(&self, preset: FilterPreset) -> bool {
+        self.presets.contains(&preset)
+    }
+
+    // Turns the preset on, or off if it was on. Nightcore and vaporwave
+    // replace each other. Returns whether it is now on.
+    pub fn toggle(&mut self, preset: FilterPreset) -> bool {
+        if self.has(preset) {
+            self.presets.retain(|active| *active != preset);
+            return false;
+        }
+
+        if preset.rate().is_some() {
+            self.presets.retain(|active| active.rate().is_none());
+        }
+        self.presets.push(preset);
+        true
+    }
+
+    fn pitch_ratio(&self) -> f64 {
+        2f64.powf(self.pitch.unwrap_or(0.0) / 12.0)
+    }
+
+    // Resampling moves speed and pitch together: the presets' rate and the
+    // pitch shift are done that way...
+    fn rate(&self) -> f64 {
+        let presets: f64 = self.presets.iter().filter_map(|preset| preset.rate()).product();
+        presets * self.pitch_ratio()
+    }
+
+    // ...and the time stretch before it makes up the speed the pitch shift
+    // shouldn't have changed
+    fn tempo(&self) -> f64 {
+        self.speed.unwrap_or(1.0) / self.pitch_ratio()
+    }
+
+    // How much faster than normal the track goes by, all filters together
+    pub fn playback_speed(&self) -> f64 {
+        self.rate() * self.tempo()
+    }
+
+    // One line per active filter, for messages
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.presets.iter().map(|preset| preset.name().to_string()).collect();
+        if !self.equalizer.is_empty() {
+            let bands: Vec<String> = self.equalizer.iter().map(EqBand::to_string).collect();
+            lines.push(format!("Equalizer: {}", bands.join(", ")));
+        }
+        if let Some(speed) = self.speed {
+            lines.push(format!("Speed {}x", speed));
+        }
+        if let Some(pitch) = self.pitch {
+            lines.push(format!("Pitch {:+} semitones", pitch));
+        }
+
+        lines
+    }
+}
+
+struct FilterState {
+    settings: StdRwLock<FilterSettings>,
+    // Bumped on every change, so playing tracks know to rebuild their chain
+    version: AtomicU64,
+}
+
+// One guild's filters, shared with every track queued there. Read from
+// songbird's audio threads, hence the std lock.
+#[derive(Clone)]
+pub struct AudioFilters {
+    state: Arc<FilterState>,
+}
+
+impl Default for AudioFilters {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(FilterState {
+                settings: StdRwLock::new(FilterSettings::default()),
+                version: AtomicU64::new(0),
+            }),
+        }
+    }
+}
+
+impl AudioFilters {
+    pub fn get(&self) -> FilterSettings {
+        self.state.settings.read().expect("Filter lock poisoned").clone()
+    }
+
+    pub fn update<F>(&self, change: F) -> FilterSettings
+    where
+        F: FnOnce(&mut FilterSettings),
+    {
+        let mut settings = self.state.settings.write().expect("Filter lock poisoned");
+        change(&mut settings);
+        self.state.version.fetch_add(1, Ordering::AcqRel);
+
+        settings.clone()
+    }
+
+    // Follows the guild setting, without disturbing playing tracks when it
+    // is unchanged
+    pub fn set_normalize(&self, normalize: bool) {
+        if self.get().normalize != normalize {
+            self.update(|settings| settings.normalize = normalize);
+        }
+    }
+
+    fn version(&self) -> u64 {
+        self.state.version.load(Ordering::Acquire)
+    }
+
+    // Puts the filter stage in front of a track's audio. Inputs songbird
+    // hasn't opened yet are wrapped to be filtered once it does; ones already
+    // opened, e.g. attachments read for their tags, are wrapped as they are.
+    pub fn wrap(&self, input: Input, progress: &PlaybackProgress) -> Input {
+        match input {
+            Input::Lazy(compose) => Input::Lazy(Box::new(FilteredCompose::new(compose, self.clone(), progress.clone()))),
+            Input::Live(LiveInput::Parsed(parsed), compose) => {
+                let compose = compose.map(|compose| {
+                    Box::new(FilteredCompose::new(compose, self.clone(), progress.clone())) as Box<_>
+                });
+                match FilterSource::new(parsed, self.clone(), progress.clone()) {
+                    Ok(source) => Input::Live(LiveInput::Raw(source.into_stream()), compose),
+                    Err(parsed) => Input::Live(LiveInput::Parsed(parsed), compose),
+                }
+            }
+            // Opened but not parsed: start over from the source, filtered
+            Input::Live(_, Some(compose)) => Input::Lazy(Box::new(FilteredCompose::new(compose, self.clone(), progress.clone()))),
+            Input::Live(live, None) => {
+                debug!("AudioFilters: Can't filter an input without its source");
+                Input::Live(live, None)
+            }
+        }
+    }
+}
+
+// Where in the song a filtered track is, and the speed it plays at from
+// there. Kept with the track's metadata: songbird's position counts what was
+// played, which drifts from the song once the speed changed mid-track.
+#[derive(Clone, Debug, Default)]
+pub struct PlaybackProgress(Arc<StdRwLock<Option<(Duration, f64)>>>);
+
+impl PlaybackProgress {
+    // `None` until the filters play the track
+    pub fn get(&self) -> Option<(Duration, f64)> {
+        *self.0.read().expect("Progress lock poisoned")
+    }
+
+    fn set(&self, position: Duration, speed: f64) {
+        *self.0.write().expect("Progress lock poisoned") = Some((position, speed));
+    }
+}
+
+// Filters are kept per guild for as long as the bot runs
+#[derive(Clone, Default)]
+pub struct FilterStore {
+    guilds: Arc<RwLock<HashMap<GuildId, AudioFilters>>>,
+}
+
+impl FilterStore {
+    pub async fn get(&self, guild_id: GuildId) -> AudioFilters {
+        if let Some(filters) = self.guilds.read().await.get(&guild_id) {
+            return filters.clone();
+        }
+
+        self.guilds.write().await.entry(guild_id).or_default().clone()
+    }
+}
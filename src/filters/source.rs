@@ -0,0 +1,482 @@
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use serenity::async_trait;
+use songbird::input::codecs::{get_codec_registry, get_probe};
+use songbird::input::{AudioStream, AudioStreamError, AuxMetadata, Compose, LiveInput, Parsed, RawAdapter};
+use symphonia::core::audio::{SampleBuffer, SignalSpec};
+use symphonia::core::codecs::Decoder;
+use symphonia::core::errors::Error as SymphError;
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSource;
+use symphonia::core::units::Time;
+use tracing::debug;
+
+use super::dsp::{FilterChain, Frame};
+use super::{AudioFilters, PlaybackProgress};
+
+// Songbird reads the filtered audio back as raw stereo `f32` PCM
+const CHANNELS: u32 = 2;
+const FRAME_BYTES: u64 = 4 * CHANNELS as u64;
+
+// A stretch of output played at one speed, from the output frame and the
+// source frame it started at
+#[derive(Clone, Copy)]
+struct Span {
+    output: u64,
+    source: u64,
+    speed: f64,
+}
+
+impl Span {
+    fn source_at(&self, output: u64) -> u64 {
+        self.source + (output.saturating_sub(self.output) as f64 * self.speed) as u64
+    }
+}
+
+// Decodes a parsed track, runs it through the guild's filters and serves the
+// result as raw PCM. Positions are in frames of the filtered output, which is
+// what songbird counts; a seek finds the spot in the source through the spans
+// played at each speed, as the speed may have changed mid-track.
+pub struct FilterSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    seekable: bool,
+    sample_rate: u32,
+    filters: AudioFilters,
+    // Settings version the chain was built for
+    version: u64,
+    chain: FilterChain,
+    samples: Option<(SampleBuffer<f32>, u64, SignalSpec)>,
+    frames: Vec<Frame>,
+    filtered: Vec<Frame>,
+    // Filtered bytes not read yet
+    pending: Vec<u8>,
+    pending_pos: usize,
+    // Bytes of output served
+    position: u64,
+    // Source frames decoded
+    source_frames: u64,
+    // Starts at output frame 0, one more for each speed change
+    spans: Vec<Span>,
+    progress: PlaybackProgress,
+}
+
+impl FilterSource {
+    // Hands the track back if it doesn't say its sample rate
+    pub fn new(parsed: Parsed, filters: AudioFilters, progress: PlaybackProgress) -> Result<Self, Parsed> {
+        let Some(sample_rate) = parsed.decoder.codec_params().sample_rate else {
+            return Err(parsed);
+        };
+        let version = filters.version();
+        let settings = filters.get();
+        let chain = FilterChain::new(&settings, sample_rate);
+
+        Ok(Self {
+            format: parsed.format,
+            decoder: parsed.decoder,
+            track_id: parsed.track_id,
+            seekable: parsed.supports_backseek,
+            sample_rate,
+            filters,
+            version,
+            chain,
+            samples: None,
+            frames: Vec::new(),
+            filtered: Vec::new(),
+            pending: Vec::new(),
+            pending_pos: 0,
+            position: 0,
+            source_frames: 0,
+            spans: vec![Span {
+                output: 0,
+                source: 0,
+                speed: settings.playback_speed(),
+            }],
+            progress,
+        })
+    }
+
+    pub fn into_stream(self) -> AudioStream<Box<dyn MediaSource>> {
+        let sample_rate = self.sample_rate;
+
+        AudioStream {
+            input: Box::new(RawAdapter::new(self, sample_rate, CHANNELS)),
+            hint: None,
+        }
+    }
+
+    // Only called once every filtered byte was read, so the new settings are
+    // heard from the current position
+    fn refresh_chain(&mut self) {
+        let version = self.filters.version();
+        if version == self.version {
+            return;
+        }
+
+        self.version = version;
+        let settings = self.filters.get();
+        self.chain.rebuild(&settings);
+
+        let speed = settings.playback_speed();
+        if self.spans.last().is_some_and(|span| span.speed != speed) {
+            self.start_span(self.position / FRAME_BYTES, self.source_frames, speed);
+        }
+    }
+
+    // Anything after `output` is played from `source` at `speed`
+    fn start_span(&mut self, output: u64, source: u64, speed: f64) {
+        self.spans.retain(|span| span.output < output);
+        self.spans.push(Span { output, source, speed });
+    }
+
+    fn report_progress(&self) {
+        let position = Duration::from_secs_f64(self.source_frames as f64 / self.sample_rate as f64);
+        let speed = self.spans.last().map_or(1.0, |span| span.speed);
+        self.progress.set(position, speed);
+    }
+
+    // Decodes and filters packets until there is something to read. `false`
+    // at the end of the track.
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+                Err(SymphError::ResetRequired) => return Ok(false),
+                Err(e) => return Err(io::Error::other(e)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphError::DecodeError(e)) => {
+                    debug!("FilterSource: Skipping undecodable packet: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(io::Error::other(e)),
+            };
+            if decoded.frames() == 0 {
+                continue;
+            }
+
+            let spec = *decoded.spec();
+            let capacity = decoded.capacity() as u64;
+            let (samples, _, _) = match &mut self.samples {
+                Some(buffer) if buffer.1 >= capacity && buffer.2 == spec => buffer,
+                samples => samples.insert((SampleBuffer::new(capacity, spec), capacity, spec)),
+            };
+            samples.copy_interleaved_ref(decoded);
+
+            // Mono is spread to both sides, anything past stereo is dropped
+            let channels = spec.channels.count();
+            self.frames.clear();
+            self.frames.extend(
+                samples
+                    .samples()
+                    .chunks_exact(channels)
+                    .map(|frame| [frame[0], frame[channels.min(2) - 1]]),
+            );
+
+            self.refresh_chain();
+            self.chain.process(&self.frames, &mut self.filtered);
+            self.source_frames += self.frames.len() as u64;
+            self.report_progress();
+
+            self.pending.clear();
+            self.pending_pos = 0;
+            for sample in self.filtered.iter().flatten() {
+                self.pending.extend_from_slice(&sample.to_le_bytes());
+            }
+            if !self.pending.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl Read for FilterSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending_pos >= self.pending.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.pending.len() - self.pending_pos);
+        buf[..len].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + len]);
+        self.pending_pos += len;
+        self.position += len as u64;
+
+        Ok(len)
+    }
+}
+
+impl Seek for FilterSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(target) => target,
+            SeekFrom::Current(0) => return Ok(self.position),
+            _ => return Err(ErrorKind::Unsupported.into()),
+        };
+
+        // Sped up, a second of output holds more than a second of the song
+        let frame = target / FRAME_BYTES;
+        let span = self
+            .spans
+            .iter()
+            .rev()
+            .find(|span| span.output <= frame)
+            .copied()
+            .unwrap_or(self.spans[0]);
+        let source = span.source_at(frame);
+        let time = Time::from(source as f64 / self.sample_rate as f64);
+        self.format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time,
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(io::Error::other)?;
+        self.decoder.reset();
+
+        self.version = self.filters.version();
+        let settings = self.filters.get();
+        self.chain.rebuild(&settings);
+        self.pending.clear();
+        self.pending_pos = 0;
+        self.position = frame * FRAME_BYTES;
+        self.source_frames = source;
+        self.start_span(frame, source, settings.playback_speed());
+        self.report_progress();
+
+        Ok(self.position)
+    }
+}
+
+impl MediaSource for FilterSource {
+    fn is_seekable(&self) -> bool {
+        self.seekable
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+// Opens the source like songbird would, then puts the filters in between
+fn filter_stream(
+    stream: AudioStream<Box<dyn MediaSource>>,
+    filters: AudioFilters,
+    progress: PlaybackProgress,
+) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+    let parsed = match LiveInput::Raw(stream).promote(get_codec_registry(), get_probe()) {
+        Ok(LiveInput::Parsed(parsed)) => parsed,
+        Ok(_) => return Err(AudioStreamError::Unsupported),
+        Err(e) => return Err(AudioStreamError::Fail(Box::new(e))),
+    };
+
+    FilterSource::new(parsed, filters, progress)
+        .map(FilterSource::into_stream)
+        .map_err(|_| AudioStreamError::Fail("the track has no sample rate".into()))
+}
+
+// A source songbird opens when the track comes up, filtered once it's open.
+// Songbird also comes back here to restart a track it can't seek back in.
+pub struct FilteredCompose {
+    inner: Box<dyn Compose>,
+    filters: AudioFilters,
+    progress: PlaybackProgress,
+}
+
+impl FilteredCompose {
+    pub fn new(inner: Box<dyn Compose>, filters: AudioFilters, progress: PlaybackProgress) -> Self {
+        Self {
+            inner,
+            filters,
+            progress,
+        }
+    }
+}
+
+#[async_trait]
+impl Compose for FilteredCompose {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let stream = self.inner.create()?;
+        filter_stream(stream, self.filters.clone(), self.progress.clone())
+    }
+
+    async fn create_async(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let stream = self.inner.create_async().await?;
+        let filters = self.filters.clone();
+        let progress = self.progress.clone();
+
+        // Probing reads from the stream, which blocks
+        tokio::task::spawn_blocking(move || filter_stream(stream, filters, progress))
+            .await
+            .map_err(|e| AudioStreamError::Fail(Box::new(e)))?
+    }
+
+    fn should_create_async(&self) -> bool {
+        self.inner.should_create_async()
+    }
+
+    async fn aux_metadata(&mut self) -> Result<AuxMetadata, AudioStreamError> {
+        self.inner.aux_metadata().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::io::Cursor;
+
+    use songbird::input::Input;
+
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    // A mono 16 bit WAV of `samples`
+    fn mono_wav(samples: impl ExactSizeIterator<Item = f32>) -> Vec<u8> {
+        let data_len = samples.len() as u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&RATE.to_le_bytes());
+        wav.extend_from_slice(&(RATE * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes());
+        }
+        wav
+    }
+
+    // Samples rising from 0 to 1 over the track, so each says where in the
+    // track it is
+    fn ramp_wav(seconds: u32) -> Vec<u8> {
+        let frames = RATE * seconds;
+        mono_wav((0..frames).map(|n| n as f32 / frames as f32))
+    }
+
+    fn parse(wav: Vec<u8>) -> Parsed {
+        let stream = AudioStream {
+            input: Box::new(Cursor::new(wav)) as Box<dyn MediaSource>,
+            hint: None,
+        };
+        let Ok(LiveInput::Parsed(parsed)) = LiveInput::Raw(stream).promote(get_codec_registry(), get_probe()) else {
+            panic!("test audio not parsed");
+        };
+        parsed
+    }
+
+    fn filter_source(wav: Vec<u8>, filters: AudioFilters) -> FilterSource {
+        FilterSource::new(parse(wav), filters, PlaybackProgress::default()).unwrap_or_else(|_| panic!("test WAV has no sample rate"))
+    }
+
+    fn read_frames(source: &mut FilterSource, frames: usize) -> Vec<f32> {
+        let mut bytes = vec![0; frames * FRAME_BYTES as usize];
+        source.read_exact(&mut bytes).unwrap();
+        bytes
+            .chunks_exact(FRAME_BYTES as usize)
+            .map(|frame| f32::from_le_bytes(frame[..4].try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn seek_scales_by_playback_speed() {
+        let filters = AudioFilters::default();
+        filters.update(|settings| settings.speed = Some(2.0));
+        let mut source = filter_source(ramp_wav(10), filters);
+
+        // Two seconds of output at double speed are four of the song
+        let target = 2 * RATE as u64 * FRAME_BYTES;
+        assert_eq!(source.seek(SeekFrom::Start(target)).unwrap(), target);
+
+        // Past the fade in of the first stretched piece
+        let frames = read_frames(&mut source, RATE as usize / 2);
+        let heard = frames[frames.len() - 1];
+        let expected = (4.0 + 2.0 * 0.5) / 10.0;
+        assert!((heard - expected).abs() < 0.02, "at {} instead of {}", heard, expected);
+
+        // Output is counted as it is read
+        assert_eq!(source.stream_position().unwrap(), target + RATE as u64 / 2 * FRAME_BYTES);
+    }
+
+    #[test]
+    fn seek_after_a_speed_change_follows_each_speed() {
+        let filters = AudioFilters::default();
+        filters.update(|settings| settings.speed = Some(2.0));
+        let mut source = filter_source(ramp_wav(10), filters.clone());
+
+        // Two seconds at double speed and one at normal speed end five in
+        read_frames(&mut source, 2 * RATE as usize);
+        filters.update(|settings| settings.speed = None);
+        read_frames(&mut source, RATE as usize);
+
+        // Songbird counts three seconds played, the song is five in
+        let (position, speed) = source.progress.get().unwrap();
+        assert!((position.as_secs_f64() - 5.0).abs() < 0.2, "{:?} into the song", position);
+        assert_eq!(speed, 1.0);
+
+        let heard_after = |source: &mut FilterSource, output_seconds: u64| {
+            let target = output_seconds * RATE as u64 * FRAME_BYTES;
+            assert_eq!(source.seek(SeekFrom::Start(target)).unwrap(), target);
+            let frames = read_frames(source, RATE as usize / 2);
+            frames[frames.len() - 1]
+        };
+
+        // Ahead at normal speed
+        let heard = heard_after(&mut source, 4);
+        let expected = (5.0 + 1.0 + 0.5) / 10.0;
+        assert!((heard - expected).abs() < 0.02, "at {} instead of {}", heard, expected);
+
+        // Back into the part played at double speed
+        let heard = heard_after(&mut source, 1);
+        let expected = (2.0 + 0.5) / 10.0;
+        assert!((heard - expected).abs() < 0.02, "at {} instead of {}", heard, expected);
+    }
+
+    #[test]
+    fn normalize_reaches_tracks_queued_before_it() {
+        let filters = AudioFilters::default();
+        let quiet = (0..RATE * 8).map(|n| 0.05 * (2.0 * PI * 440.0 * n as f32 / RATE as f32).sin());
+        let input = filters.wrap(
+            Input::Live(LiveInput::Parsed(parse(mono_wav(quiet))), None),
+            &PlaybackProgress::default(),
+        );
+
+        filters.set_normalize(true);
+
+        let Input::Live(live, _) = input else {
+            panic!("wrapped input not live");
+        };
+        let Ok(LiveInput::Parsed(mut parsed)) = live.promote(get_codec_registry(), get_probe()) else {
+            panic!("filtered audio not parsed");
+        };
+        let mut samples = Vec::new();
+        while let Ok(packet) = parsed.format.next_packet() {
+            let decoded = parsed.decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+
+        // Turned up once the gain has settled
+        let tail = &samples[samples.len() - 2 * RATE as usize * CHANNELS as usize..];
+        let peak = tail.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak > 0.1, "peak {} after turning normalization on", peak);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use rusqlite::{params, params_from_iter, Connection};
+use serenity::model::id::UserId;
+use songbird::input::codecs::get_probe;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tracing::{debug, info, warn};
+
+use crate::commands::music::attachment::{FileTags, AUDIO_EXTENSIONS};
+use crate::commands::music::metadata::{StreamTitle, TrackMetadata, TrackSource};
+use crate::filters::PlaybackProgress;
+
+// An index of the audio files below `library_path`, searched by tags and
+// file name. Kept in the settings database so restarts don't rescan.
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS library_tracks (
+    id          INTEGER PRIMARY KEY,
+    path        TEXT NOT NULL UNIQUE,
+    modified    INTEGER NOT NULL,
+    title       TEXT NOT NULL,
+    artist      TEXT,
+    album       TEXT,
+    duration_ms INTEGER
+);
+";
+
+#[derive(Clone, Debug)]
+pub struct LibraryTrack {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl LibraryTrack {
+    pub fn display_title(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("{} - {}", artist, self.title),
+            None => self.title.clone(),
+        }
+    }
+
+    pub fn to_metadata(&self, requester_id: UserId, requester_name: &str) -> TrackMetadata {
+        TrackMetadata {
+            title: self.display_title(),
+            url: self.path.to_string_lossy().into_owned(),
+            duration: self.duration,
+            requester_id,
+            requester_name: requester_name.to_string(),
+            autoplay: false,
+            source: TrackSource::File,
+            stream_title: StreamTitle::default(),
+            progress: PlaybackProgress::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    pub total: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+#[derive(Clone)]
+pub struct Library {
+    root: PathBuf,
+    conn: Arc<Mutex<Connection>>,
+    scanning: Arc<AtomicBool>,
+}
+
+impl Library {
+    pub fn open(root: &Path, database_path: &Path) -> Result<Self, Box<dyn Error>> {
+        if let Some(parent) = database_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(database_path)?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            conn: Arc::new(Mutex::new(conn)),
+            scanning: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    async fn call<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            f(&mut conn.lock().expect("Library connection poisoned"))
+        })
+        .await
+        .expect("Library task panicked")
+    }
+
+    pub async fn count(&self) -> rusqlite::Result<usize> {
+        self.call(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM library_tracks", [], |row| {
+                row.get::<_, i64>(0)
+            })
+        })
+        .await
+        .map(|count| count as usize)
+    }
+
+    // Every word has to appear in the title, artist, album or path
+    pub async fn search(&self, query: &str, limit: usize) -> rusqlite::Result<Vec<LibraryTrack>> {
+        let words: Vec<String> = query
+            .split_whitespace()
+            .map(|word| {
+                let escaped = word
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_");
+                format!("%{}%", escaped)
+            })
+            .collect();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.call(move |conn| {
+            let conditions = vec![
+                "(title LIKE ? ESCAPE '\\' OR artist LIKE ? ESCAPE '\\' \
+                 OR album LIKE ? ESCAPE '\\' OR path LIKE ? ESCAPE '\\')";
+                words.len()
+            ];
+            let sql = format!(
+                "SELECT path, title, artist, album, duration_ms FROM library_tracks
+                 WHERE {}
+                 ORDER BY artist, album, title
+                 LIMIT {}",
+                conditions.join(" AND "),
+                limit
+            );
+            let params = words.iter().flat_map(|word| [word; 4]);
+
+            conn.prepare(&sql)?
+                .query_map(params_from_iter(params), |row| {
+                    Ok(LibraryTrack {
+                        path: PathBuf::from(row.get::<_, String>(0)?),
+                        title: row.get(1)?,
+                        artist: row.get(2)?,
+                        album: row.get(3)?,
+                        duration: row
+                            .get::<_, Option<i64>>(4)?
+                            .map(|ms| Duration::from_millis(ms as u64)),
+                    })
+                })?
+                .collect()
+        })
+        .await
+    }
+
+    // Walks the library folder, reading tags of new and changed files only.
+    // Returns `None` if a scan is already running.
+    pub async fn scan(&self) -> Option<Result<ScanReport, Box<dyn Error + Send + Sync>>> {
+        if self.scanning.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        let library = self.clone();
+        let result = tokio::task::spawn_blocking(move || library.scan_blocking())
+            .await
+            .expect("Library scan panicked");
+        self.scanning.store(false, Ordering::SeqCst);
+
+        match &result {
+            Ok(report) => info!(
+                "Library scan of {} done: {} files, {} updated, {} removed, {} unreadable",
+                self.root.display(),
+                report.total,
+                report.updated,
+                report.removed,
+                report.failed
+            ),
+            Err(e) => warn!("Library scan of {} failed: {}", self.root.display(), e),
+        }
+
+        Some(result)
+    }
+
+    fn scan_blocking(&self) -> Result<ScanReport, Box<dyn Error + Send + Sync>> {
+        let known: HashMap<String, i64> = {
+            let conn = self.conn.lock().expect("Library connection poisoned");
+            let mut statement = conn.prepare("SELECT path, modified FROM library_tracks")?;
+            let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let mut files = Vec::new();
+        collect_audio_files(&self.root, &mut files);
+
+        let mut report = ScanReport {
+            total: files.len(),
+            ..ScanReport::default()
+        };
+        let mut seen = HashSet::new();
+        let mut changed = Vec::new();
+        for (path, modified) in files {
+            let key = path.to_string_lossy().into_owned();
+            if known.get(&key) != Some(&modified) {
+                match read_file_tags(&path) {
+                    Ok(tags) => changed.push((key.clone(), modified, tags)),
+                    Err(e) => {
+                        debug!("Library: Could not read {}: {}", path.display(), e);
+                        report.failed += 1;
+                    }
+                }
+            }
+            seen.insert(key);
+        }
+
+        let mut conn = self.conn.lock().expect("Library connection poisoned");
+        let tx = conn.transaction()?;
+        for (path, modified, tags) in &changed {
+            let title = tags.title.clone().unwrap_or_else(|| file_stem(path));
+            tx.execute(
+                "INSERT INTO library_tracks (path, modified, title, artist, album, duration_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (path) DO UPDATE SET modified = ?2, title = ?3, artist = ?4,
+                     album = ?5, duration_ms = ?6",
+                params![
+                    path,
+                    modified,
+                    title,
+                    tags.artist,
+                    tags.album,
+                    tags.duration.map(|d| d.as_millis() as i64)
+                ],
+            )?;
+        }
+        for path in known.keys().filter(|path| !seen.contains(*path)) {
+            tx.execute("DELETE FROM library_tracks WHERE path = ?1", params![path])?;
+            report.removed += 1;
+        }
+        tx.commit()?;
+
+        report.updated = changed.len();
+        Ok(report)
+    }
+}
+
+// Unreadable folders are skipped rather than failing the whole scan
+fn collect_audio_files(dir: &Path, files: &mut Vec<(PathBuf, i64)>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Library: Could not read folder {}: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        if file_type.is_dir() {
+            collect_audio_files(&path, files);
+        } else if file_type.is_file() && has_audio_extension(&path) {
+            let modified = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_secs() as i64);
+            files.push((path, modified));
+        }
+    }
+}
+
+fn has_audio_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            AUDIO_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+fn file_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn read_file_tags(path: &Path) -> Result<FileTags, Box<dyn Error + Send + Sync>> {
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut probed = get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    // Tags inside the container win over ones found while probing
+    let mut tags = FileTags::default();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        tags.read_revision(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.read_revision(revision);
+    }
+    if let Some(track) = probed.format.default_track() {
+        tags.read_duration(&track.codec_params);
+    }
+
+    Ok(tags)
+}
@@ -1,12 +1,22 @@
+mod cache;
 mod commands;
+mod config;
+mod filters;
+mod library;
+mod playlists;
+mod radio;
+mod settings;
+mod shutdown;
+mod snapshot;
+mod ytdlp;
 
 use std::collections::HashSet;
-use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 
 use serenity::async_trait;
 use serenity::framework::standard::macros::{group, hook};
-use serenity::framework::standard::{Configuration, StandardFramework};
+use serenity::framework::standard::{Configuration, DispatchError, StandardFramework};
 use serenity::gateway::{ActivityData, ShardManager};
 use serenity::http::Http;
 use serenity::model::channel::Message;
@@ -14,20 +24,47 @@ use serenity::model::event::ResumedEvent;
 use serenity::model::gateway::Ready;
 
 use serenity::prelude::*;
-use songbird::SerenityInit;
-use tracing::{debug, info, instrument};
+use songbird::{SerenityInit, Songbird};
+use tracing::{debug, error, info, instrument, warn};
 
+use crate::commands::cache::*;
 use crate::commands::help::*;
+use crate::commands::settings::*;
+use crate::commands::utils::{get_guild_settings, get_prefix, has_dj_access, send_warning};
 
+use crate::commands::music::autoplay::*;
 use crate::commands::music::clear::*;
 use crate::commands::music::leave::*;
+use crate::commands::music::library::*;
 use crate::commands::music::current::*;
+use crate::commands::music::filter::*;
+use crate::commands::music::history::*;
 use crate::commands::music::pause::*;
+use crate::commands::music::pitch::*;
 use crate::commands::music::play::*;
+use crate::commands::music::playlist::*;
+use crate::commands::music::previous::*;
+use crate::commands::music::queue::*;
+use crate::commands::music::radio::*;
 use crate::commands::music::resume::*;
+use crate::commands::music::search::*;
 use crate::commands::music::skip::*;
+use crate::commands::music::speed::*;
 use crate::commands::music::stop::*;
 
+use crate::cache::TrackCache;
+use crate::commands::music::crossfade::Crossfades;
+use crate::commands::music::resolver::ResolverRegistry;
+use crate::config::Config;
+use crate::filters::FilterStore;
+use crate::library::Library;
+use crate::playlists::PlaylistStore;
+use crate::radio::RadioStore;
+use crate::settings::GuildSettingsStore;
+use crate::shutdown::{CommandChannels, Shutdown};
+use crate::snapshot::SnapshotStore;
+use crate::ytdlp::YtDlp;
+
 use reqwest::Client as HttpClient;
 
 pub struct HttpKey;
@@ -36,10 +73,89 @@ impl TypeMapKey for HttpKey {
     type Value = HttpClient;
 }
 
+pub struct ConfigKey;
+
+impl TypeMapKey for ConfigKey {
+    type Value = &'static Config;
+}
+
+pub struct TrackCacheKey;
+
+impl TypeMapKey for TrackCacheKey {
+    type Value = TrackCache;
+}
+
+pub struct YtDlpKey;
+
+impl TypeMapKey for YtDlpKey {
+    type Value = YtDlp;
+}
+
+pub struct ResolverKey;
+
+impl TypeMapKey for ResolverKey {
+    type Value = Arc<ResolverRegistry>;
+}
+
+pub struct TrackHistoryKey;
+
+impl TypeMapKey for TrackHistoryKey {
+    type Value = TrackHistory;
+}
+
+pub struct FilterStoreKey;
+
+impl TypeMapKey for FilterStoreKey {
+    type Value = FilterStore;
+}
+
+pub struct CrossfadesKey;
+
+impl TypeMapKey for CrossfadesKey {
+    type Value = Crossfades;
+}
+
+pub struct GuildSettingsKey;
+
+impl TypeMapKey for GuildSettingsKey {
+    type Value = GuildSettingsStore;
+}
+
+pub struct PlaylistStoreKey;
+
+impl TypeMapKey for PlaylistStoreKey {
+    type Value = PlaylistStore;
+}
+
+pub struct RadioStoreKey;
+
+impl TypeMapKey for RadioStoreKey {
+    type Value = RadioStore;
+}
+
+// Only present when `library_path` is configured
+pub struct LibraryKey;
+
+impl TypeMapKey for LibraryKey {
+    type Value = Library;
+}
+
+pub struct SnapshotKey;
+
+impl TypeMapKey for SnapshotKey {
+    type Value = Arc<SnapshotStore>;
+}
+
 pub struct ShardManagerContainer;
 
 impl TypeMapKey for ShardManagerContainer {
-    type Value = Arc<Mutex<ShardManager>>;
+    type Value = Arc<ShardManager>;
+}
+
+pub struct CommandChannelsKey;
+
+impl TypeMapKey for CommandChannelsKey {
+    type Value = CommandChannels;
 }
 
 struct Handler;
@@ -51,9 +167,22 @@ impl EventHandler for Handler {
             "Connected as --> {} [id: {}]",
             ready.user.name, ready.user.id
         );
-        let status =
-            env::var("DISCORD_STATUS").expect("Set your DISCORD_STATUS environment variable!");
-        ctx.set_activity(Some(ActivityData::playing(status)));
+        let (config, snapshots) = {
+            let data = ctx.data.read().await;
+            (
+                data.get::<ConfigKey>()
+                    .copied()
+                    .expect("Should exist in typemap"),
+                data.get::<SnapshotKey>()
+                    .cloned()
+                    .expect("Should exist in typemap"),
+            )
+        };
+        ctx.set_activity(Some(ActivityData::playing(config.status.as_str())));
+
+        tokio::spawn(async move {
+            snapshots.restore(&ctx).await;
+        });
     }
 
     #[instrument(skip(self, _ctx))]
@@ -64,18 +193,62 @@ impl EventHandler for Handler {
 
 #[hook]
 #[instrument]
-async fn before(_: &Context, msg: &Message, command_name: &str) -> bool {
+async fn before(ctx: &Context, msg: &Message, command_name: &str) -> bool {
     info!(
         "Received command --> '{}' || User --> '{}'",
         command_name, msg.author.name
     );
+
+    if let Some(guild_id) = msg.guild_id {
+        let channels = {
+            let data = ctx.data.read().await;
+            data.get::<CommandChannelsKey>()
+                .cloned()
+                .expect("Should exist in typemap")
+        };
+        channels.record(guild_id, msg.channel_id).await;
+
+        if DJ_COMMANDS.contains(&command_name) {
+            if let Some(dj_role) = get_guild_settings(ctx, guild_id).await.dj_role {
+                if !has_dj_access(ctx, msg, dj_role).await {
+                    let _ = send_warning(ctx, msg, "Only DJs can control playback here.").await;
+                    return false;
+                }
+            }
+        }
+    }
+
     true
 }
 
+#[hook]
+async fn dynamic_prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    Some(get_prefix(ctx, msg).await)
+}
+
+#[hook]
+async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError, command_name: &str) {
+    if let DispatchError::LackingPermissions(permissions) = error {
+        let _ = send_warning(
+            ctx,
+            msg,
+            &format!("You need the {} permission to use this command.", permissions),
+        )
+        .await;
+    } else if let DispatchError::OnlyForOwners = error {
+        let _ = send_warning(ctx, msg, "Only the bot owner can use this command.").await;
+    } else {
+        debug!("Command '{}' was not dispatched: {:?}", command_name, error);
+    }
+}
+
 #[group]
-#[commands(help, leave, play, pause, resume, clear, skip, stop, current)]
+#[commands(help, cache, leave, play, search, pause, resume, clear, skip, stop, current, history, previous, queue, autoplay, filter, speed, pitch, playlist, radio, library, settings)]
 struct General;
 
+// Commands restricted to the DJ role once a guild has set one
+const DJ_COMMANDS: &[&str] = &["leave", "pause", "resume", "clear", "skip", "stop", "previous", "autoplay", "radio", "filter", "speed", "pitch"];
+
 #[cfg(feature = "development")]
 fn init_env() {
     dotenv::dotenv().ok();
@@ -91,10 +264,19 @@ fn init_env() {
 async fn main() {
     init_env();
 
-    let token = env::var("DISCORD_TOKEN").expect("Set your DISCORD_TOKEN environment variable!");
-    let prefix = env::var("PREFIX").expect("Set your PREFIX environment variable!");
+    let config: &'static Config = match Config::load() {
+        Ok(config) => Box::leak(Box::new(config)),
+        Err(why) => {
+            eprintln!("Invalid configuration: {}", why);
+            std::process::exit(2);
+        }
+    };
+
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level())
+        .init();
 
-    let http = Http::new(&token);
+    let http = Http::new(&config.token);
 
     let (owners, _bot_id) = match http.get_current_application_info().await {
         Ok(info) => {
@@ -106,31 +288,182 @@ async fn main() {
         Err(why) => panic!("Could not access application info: {:?}", why),
     };
 
-    tracing_subscriber::fmt::init();
+    let settings = match GuildSettingsStore::open(&config.database_path) {
+        Ok(settings) => settings,
+        Err(why) => {
+            eprintln!(
+                "Could not open settings database {}: {}",
+                config.database_path.display(),
+                why
+            );
+            std::process::exit(2);
+        }
+    };
+
+    let playlists = match PlaylistStore::open(&config.database_path) {
+        Ok(playlists) => playlists,
+        Err(why) => {
+            eprintln!(
+                "Could not open playlist database {}: {}",
+                config.database_path.display(),
+                why
+            );
+            std::process::exit(2);
+        }
+    };
+
+    let cache = match TrackCache::open(config) {
+        Ok(cache) => cache,
+        Err(why) => {
+            eprintln!("Could not open track cache {}: {}", config.cache_path.display(), why);
+            std::process::exit(2);
+        }
+    };
+
+    let radio = match RadioStore::open(&config.database_path) {
+        Ok(radio) => radio,
+        Err(why) => {
+            eprintln!(
+                "Could not open radio database {}: {}",
+                config.database_path.display(),
+                why
+            );
+            std::process::exit(2);
+        }
+    };
+
+    let library = match &config.library_path {
+        Some(path) => match Library::open(path, &config.database_path) {
+            Ok(library) => Some(library),
+            Err(why) => {
+                eprintln!(
+                    "Could not open library database {}: {}",
+                    config.database_path.display(),
+                    why
+                );
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
 
     let framework = StandardFramework::new()
+        .before(before)
+        .on_dispatch_error(dispatch_error)
         .group(&GENERAL_GROUP);
-    framework.configure(Configuration::new().prefix(prefix).owners(owners));
+    // The static prefix is cleared so guilds with their own prefix don't
+    // also answer to the default one
+    framework.configure(
+        Configuration::new()
+            .prefix("")
+            .dynamic_prefix(dynamic_prefix)
+            .owners(owners),
+    );
 
     let intents = GatewayIntents::non_privileged()
         | GatewayIntents::MESSAGE_CONTENT
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::GUILD_VOICE_STATES;
 
-    let mut client = Client::builder(&token, intents)
+    let songbird = Songbird::serenity();
+    let snapshots = Arc::new(SnapshotStore::new(&config.snapshot_path));
+    let channels = CommandChannels::default();
+
+    let http_client = HttpClient::new();
+    let ytdlp = YtDlp::new(config, http_client.clone());
+    let resolvers = ResolverRegistry::standard(
+        config,
+        http_client.clone(),
+        ytdlp.clone(),
+        cache.clone(),
+        library.clone(),
+    );
+
+    let mut client = Client::builder(&config.token, intents)
         .event_handler(Handler)
         .framework(framework)
-        .register_songbird()
-        .type_map_insert::<HttpKey>(HttpClient::new())
+        .register_songbird_with(songbird.clone())
+        .type_map_insert::<ConfigKey>(config)
+        .type_map_insert::<HttpKey>(http_client)
+        .type_map_insert::<YtDlpKey>(ytdlp)
+        .type_map_insert::<ResolverKey>(Arc::new(resolvers))
+        .type_map_insert::<TrackCacheKey>(cache)
+        .type_map_insert::<TrackHistoryKey>(TrackHistory::default())
+        .type_map_insert::<FilterStoreKey>(FilterStore::default())
+        .type_map_insert::<CrossfadesKey>(Crossfades::default())
+        .type_map_insert::<GuildSettingsKey>(settings.clone())
+        .type_map_insert::<PlaylistStoreKey>(playlists)
+        .type_map_insert::<RadioStoreKey>(radio)
+        .type_map_insert::<SnapshotKey>(snapshots.clone())
+        .type_map_insert::<CommandChannelsKey>(channels.clone())
         .await
         .expect("Err creating client");
 
+    let shard_manager = client.shard_manager.clone();
+    let http = client.http.clone();
+    {
+        let mut data = client.data.write().await;
+        data.insert::<ShardManagerContainer>(shard_manager.clone());
+        if let Some(library) = library {
+            data.insert::<LibraryKey>(library.clone());
+            // Files added while the bot was offline are picked up in the background
+            tokio::spawn(async move {
+                library.scan().await;
+            });
+        }
+    }
+
+    snapshots.clone().spawn_periodic(songbird.clone());
 
-    tokio::spawn(async move {
-        let _ = client.start().await.map_err(|why| println!("Client ended {:?}", why));
-    });
+    let mut client_task = tokio::spawn(async move { client.start().await });
 
-    let _signal_err = tokio::signal::ctrl_c().await;
-    println!("Received Ctrl-C, shutting down");
+    let exit_code = tokio::select! {
+        signal = shutdown::wait_for_signal() => {
+            info!("Received {}, shutting down", signal);
+
+            Shutdown {
+                http: &http,
+                songbird: &songbird,
+                snapshots: &snapshots,
+                shard_manager: &shard_manager,
+                channels: &channels,
+                settings: &settings,
+                notice: config.shutdown_notice.as_deref(),
+            }
+            .run()
+            .await;
+
+            match tokio::time::timeout(Duration::from_secs(10), client_task).await {
+                Ok(Ok(Ok(()))) => 0,
+                Ok(Ok(Err(why))) => {
+                    error!("Client ended with an error during shutdown: {:?}", why);
+                    1
+                }
+                Ok(Err(why)) => {
+                    error!("Client task panicked during shutdown: {:?}", why);
+                    1
+                }
+                Err(_) => {
+                    warn!("Client did not stop within 10 seconds");
+                    1
+                }
+            }
+        }
+        result = &mut client_task => {
+            match result {
+                Ok(Ok(())) => 0,
+                Ok(Err(why)) => {
+                    error!("Client ended {:?}", why);
+                    1
+                }
+                Err(why) => {
+                    error!("Client task panicked: {:?}", why);
+                    1
+                }
+            }
+        }
+    };
 
+    info!("Shutdown complete");
+    std::process::exit(exit_code);
 }
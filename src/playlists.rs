@@ -0,0 +1,310 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::model::id::{GuildId, UserId};
+
+use crate::commands::music::metadata::TrackMetadata;
+
+// Named playlists saved by members, either personal (usable in every guild)
+// or shared with everyone in one guild. Kept in the settings database.
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS playlists (
+    id       INTEGER PRIMARY KEY,
+    name     TEXT NOT NULL COLLATE NOCASE,
+    scope    TEXT NOT NULL,
+    scope_id INTEGER NOT NULL,
+    owner_id INTEGER NOT NULL,
+    UNIQUE (scope, scope_id, name)
+);
+CREATE TABLE IF NOT EXISTS playlist_tracks (
+    playlist_id INTEGER NOT NULL,
+    position    INTEGER NOT NULL,
+    metadata    TEXT NOT NULL,
+    PRIMARY KEY (playlist_id, position)
+);
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistScope {
+    User(UserId),
+    Guild(GuildId),
+}
+
+impl PlaylistScope {
+    fn to_sql(self) -> (&'static str, i64) {
+        match self {
+            PlaylistScope::User(id) => ("user", id.get() as i64),
+            PlaylistScope::Guild(id) => ("guild", id.get() as i64),
+        }
+    }
+
+    fn from_sql(scope: &str, id: i64) -> Self {
+        match scope {
+            "guild" => PlaylistScope::Guild(GuildId::new(id as u64)),
+            _ => PlaylistScope::User(UserId::new(id as u64)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Playlist {
+    pub id: i64,
+    pub name: String,
+    pub scope: PlaylistScope,
+    pub owner_id: UserId,
+    pub tracks: Vec<TrackMetadata>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlaylistSummary {
+    pub name: String,
+    pub scope: PlaylistScope,
+    pub track_count: usize,
+}
+
+#[derive(Clone)]
+pub struct PlaylistStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl PlaylistStore {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn call<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            f(&mut conn.lock().expect("Playlist connection poisoned"))
+        })
+        .await
+        .expect("Playlist task panicked")
+    }
+
+    pub async fn get(&self, scope: PlaylistScope, name: &str) -> rusqlite::Result<Option<Playlist>> {
+        let name = name.to_string();
+        self.call(move |conn| find_in_scope(conn, scope, &name)).await
+    }
+
+    // A member's own playlist wins over a guild playlist of the same name
+    pub async fn find(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+        name: &str,
+    ) -> rusqlite::Result<Option<Playlist>> {
+        let name = name.to_string();
+        self.call(move |conn| {
+            for scope in [PlaylistScope::User(user_id), PlaylistScope::Guild(guild_id)] {
+                if let Some(playlist) = find_in_scope(conn, scope, &name)? {
+                    return Ok(Some(playlist));
+                }
+            }
+
+            Ok(None)
+        })
+        .await
+    }
+
+    pub async fn list(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+    ) -> rusqlite::Result<Vec<PlaylistSummary>> {
+        self.call(move |conn| {
+            let (user_scope, user_scope_id) = PlaylistScope::User(user_id).to_sql();
+            let (guild_scope, guild_scope_id) = PlaylistScope::Guild(guild_id).to_sql();
+
+            conn.prepare(
+                "SELECT p.name, p.scope, p.scope_id,
+                        (SELECT COUNT(*) FROM playlist_tracks t WHERE t.playlist_id = p.id)
+                 FROM playlists p
+                 WHERE (p.scope = ?1 AND p.scope_id = ?2) OR (p.scope = ?3 AND p.scope_id = ?4)
+                 ORDER BY p.scope DESC, p.name",
+            )?
+            .query_map(
+                params![user_scope, user_scope_id, guild_scope, guild_scope_id],
+                |row| {
+                    Ok(PlaylistSummary {
+                        name: row.get(0)?,
+                        scope: PlaylistScope::from_sql(&row.get::<_, String>(1)?, row.get(2)?),
+                        track_count: row.get::<_, i64>(3)? as usize,
+                    })
+                },
+            )?
+            .collect()
+        })
+        .await
+    }
+
+    // Creates the playlist, or replaces the tracks of an existing one
+    pub async fn save(
+        &self,
+        scope: PlaylistScope,
+        owner_id: UserId,
+        name: &str,
+        tracks: Vec<TrackMetadata>,
+    ) -> rusqlite::Result<()> {
+        let name = name.to_string();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let id = match find_in_scope(&tx, scope, &name)? {
+                Some(playlist) => {
+                    tx.execute(
+                        "DELETE FROM playlist_tracks WHERE playlist_id = ?1",
+                        params![playlist.id],
+                    )?;
+                    playlist.id
+                }
+                None => {
+                    let (scope, scope_id) = scope.to_sql();
+                    tx.execute(
+                        "INSERT INTO playlists (name, scope, scope_id, owner_id) VALUES (?1, ?2, ?3, ?4)",
+                        params![name, scope, scope_id, owner_id.get() as i64],
+                    )?;
+                    tx.last_insert_rowid()
+                }
+            };
+
+            for (position, metadata) in tracks.iter().enumerate() {
+                insert_track(&tx, id, position, metadata)?;
+            }
+
+            tx.commit()
+        })
+        .await
+    }
+
+    pub async fn add_track(&self, playlist_id: i64, metadata: TrackMetadata) -> rusqlite::Result<()> {
+        self.call(move |conn| {
+            let position: i64 = conn.query_row(
+                "SELECT COALESCE(MAX(position) + 1, 0) FROM playlist_tracks WHERE playlist_id = ?1",
+                params![playlist_id],
+                |row| row.get(0),
+            )?;
+
+            insert_track(conn, playlist_id, position as usize, &metadata)
+        })
+        .await
+    }
+
+    // `index` counts from 0 in playlist order
+    pub async fn remove_track(
+        &self,
+        playlist_id: i64,
+        index: usize,
+    ) -> rusqlite::Result<Option<TrackMetadata>> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let Some(track) = load_tracks(&tx, playlist_id)?.into_iter().nth(index) else {
+                return Ok(None);
+            };
+
+            tx.execute(
+                "DELETE FROM playlist_tracks WHERE playlist_id = ?1 AND position = ?2",
+                params![playlist_id, track.0],
+            )?;
+            tx.commit()?;
+
+            Ok(Some(track.1))
+        })
+        .await
+    }
+
+    pub async fn delete(&self, playlist_id: i64) -> rusqlite::Result<()> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM playlist_tracks WHERE playlist_id = ?1",
+                params![playlist_id],
+            )?;
+            tx.execute("DELETE FROM playlists WHERE id = ?1", params![playlist_id])?;
+            tx.commit()
+        })
+        .await
+    }
+}
+
+fn find_in_scope(
+    conn: &Connection,
+    scope: PlaylistScope,
+    name: &str,
+) -> rusqlite::Result<Option<Playlist>> {
+    let (scope_name, scope_id) = scope.to_sql();
+    let found = conn
+        .query_row(
+            "SELECT id, name, owner_id FROM playlists WHERE scope = ?1 AND scope_id = ?2 AND name = ?3",
+            params![scope_name, scope_id, name],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    let Some((id, name, owner_id)) = found else {
+        return Ok(None);
+    };
+
+    Ok(Some(Playlist {
+        id,
+        name,
+        scope,
+        owner_id: UserId::new(owner_id as u64),
+        tracks: load_tracks(conn, id)?
+            .into_iter()
+            .map(|(_, metadata)| metadata)
+            .collect(),
+    }))
+}
+
+// Positions may have gaps after removals, so tracks are returned with theirs
+fn load_tracks(conn: &Connection, playlist_id: i64) -> rusqlite::Result<Vec<(i64, TrackMetadata)>> {
+    conn.prepare(
+        "SELECT position, metadata FROM playlist_tracks WHERE playlist_id = ?1 ORDER BY position",
+    )?
+    .query_map(params![playlist_id], |row| {
+        let json: String = row.get(1)?;
+        let metadata = serde_json::from_str(&json).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+        })?;
+
+        Ok((row.get(0)?, metadata))
+    })?
+    .collect()
+}
+
+fn insert_track(
+    conn: &Connection,
+    playlist_id: i64,
+    position: usize,
+    metadata: &TrackMetadata,
+) -> rusqlite::Result<()> {
+    let json = serde_json::to_string(metadata)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    conn.execute(
+        "INSERT INTO playlist_tracks (playlist_id, position, metadata) VALUES (?1, ?2, ?3)",
+        params![playlist_id, position as i64, json],
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::model::id::{GuildId, UserId};
+
+use crate::commands::music::metadata::{StreamTitle, TrackMetadata, TrackSource};
+use crate::filters::PlaybackProgress;
+
+// Named radio stations a guild can tune in to with `radio <name>`. Kept in
+// the settings database.
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS radio_stations (
+    guild_id INTEGER NOT NULL,
+    name     TEXT NOT NULL COLLATE NOCASE,
+    url      TEXT NOT NULL,
+    direct   INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, name)
+);
+";
+
+#[derive(Clone, Debug)]
+pub struct RadioStation {
+    pub name: String,
+    pub url: String,
+    // Played straight over HTTP rather than through yt-dlp
+    pub direct: bool,
+}
+
+impl RadioStation {
+    // The station name stays the title, the current song is shown next to it
+    pub fn to_metadata(&self, requester_id: UserId, requester_name: &str) -> TrackMetadata {
+        TrackMetadata {
+            title: self.name.clone(),
+            url: self.url.clone(),
+            duration: None,
+            requester_id,
+            requester_name: requester_name.to_string(),
+            autoplay: false,
+            source: if self.direct {
+                TrackSource::Http
+            } else {
+                TrackSource::YoutubeDl
+            },
+            stream_title: StreamTitle::default(),
+            progress: PlaybackProgress::default(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RadioStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl RadioStore {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn call<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            f(&mut conn.lock().expect("Radio connection poisoned"))
+        })
+        .await
+        .expect("Radio task panicked")
+    }
+
+    pub async fn get(&self, guild_id: GuildId, name: &str) -> rusqlite::Result<Option<RadioStation>> {
+        let name = name.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                "SELECT name, url, direct FROM radio_stations WHERE guild_id = ?1 AND name = ?2",
+                params![guild_id.get() as i64, name],
+                |row| {
+                    Ok(RadioStation {
+                        name: row.get(0)?,
+                        url: row.get(1)?,
+                        direct: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+        })
+        .await
+    }
+
+    pub async fn list(&self, guild_id: GuildId) -> rusqlite::Result<Vec<RadioStation>> {
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT name, url, direct FROM radio_stations WHERE guild_id = ?1 ORDER BY name",
+            )?
+            .query_map(params![guild_id.get() as i64], |row| {
+                Ok(RadioStation {
+                    name: row.get(0)?,
+                    url: row.get(1)?,
+                    direct: row.get(2)?,
+                })
+            })?
+            .collect()
+        })
+        .await
+    }
+
+    // Adds the station, or points an existing one at a new URL
+    pub async fn save(&self, guild_id: GuildId, station: RadioStation) -> rusqlite::Result<()> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO radio_stations (guild_id, name, url, direct) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (guild_id, name) DO UPDATE SET url = ?3, direct = ?4",
+                params![guild_id.get() as i64, station.name, station.url, station.direct],
+            )?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    // Returns whether the station existed
+    pub async fn delete(&self, guild_id: GuildId, name: &str) -> rusqlite::Result<bool> {
+        let name = name.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "DELETE FROM radio_stations WHERE guild_id = ?1 AND name = ?2",
+                params![guild_id.get() as i64, name],
+            )
+            .map(|deleted| deleted > 0)
+        })
+        .await
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusqlite::{params, Connection, Row};
+use serenity::model::id::{ChannelId, GuildId, RoleId};
+use serenity::prelude::*;
+use tracing::info;
+
+use crate::commands::music::search::SearchProvider;
+use crate::config::Config;
+
+// Per-guild overrides of the global configuration, stored in a local SQLite
+// database. A setting left at `None` falls back to the value in `Config`.
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS guild_settings (
+    guild_id            INTEGER PRIMARY KEY,
+    prefix              TEXT,
+    dj_role             INTEGER,
+    default_volume      REAL,
+    announce_channel    INTEGER,
+    idle_timeout        INTEGER,
+    max_queue_length    INTEGER,
+    max_playlist_length INTEGER,
+    max_track_duration  INTEGER,
+    autoplay            INTEGER NOT NULL DEFAULT 0,
+    search_provider     TEXT,
+    normalize           INTEGER NOT NULL DEFAULT 0,
+    crossfade           INTEGER NOT NULL DEFAULT 0
+);
+";
+
+// Columns added after the table was first created, for databases that
+// predate them
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("search_provider", "TEXT"),
+    ("normalize", "INTEGER NOT NULL DEFAULT 0"),
+    ("crossfade", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct GuildSettings {
+    pub prefix: Option<String>,
+    pub dj_role: Option<RoleId>,
+    pub default_volume: Option<f32>,
+    pub announce_channel: Option<ChannelId>,
+    // Seconds
+    pub idle_timeout: Option<u64>,
+    pub max_queue_length: Option<usize>,
+    pub max_playlist_length: Option<usize>,
+    // Seconds
+    pub max_track_duration: Option<u64>,
+    pub autoplay: bool,
+    pub search_provider: Option<SearchProvider>,
+    // Loudness normalization of every track played
+    pub normalize: bool,
+    // Seconds the next track overlaps the end of the current one, 0 for none
+    pub crossfade: u64,
+}
+
+impl GuildSettings {
+    pub fn prefix<'a>(&'a self, config: &'a Config) -> &'a str {
+        self.prefix.as_deref().unwrap_or(&config.prefix)
+    }
+
+    pub fn default_volume(&self, config: &Config) -> f32 {
+        self.default_volume.unwrap_or(config.default_volume)
+    }
+
+    pub fn idle_timeout(&self, config: &Config) -> Duration {
+        Duration::from_secs(self.idle_timeout.unwrap_or(config.idle_timeout))
+    }
+
+    // 0 means unlimited, as in `Limits`
+    pub fn max_queue_length(&self, config: &Config) -> usize {
+        self.max_queue_length.unwrap_or(config.limits.max_queue_length)
+    }
+
+    pub fn max_playlist_length(&self, config: &Config) -> usize {
+        self.max_playlist_length.unwrap_or(config.limits.max_playlist_length)
+    }
+
+    pub fn max_track_duration(&self, config: &Config) -> Option<Duration> {
+        Some(self.max_track_duration.unwrap_or(config.limits.max_track_duration))
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub fn search_provider(&self) -> SearchProvider {
+        self.search_provider.unwrap_or_default()
+    }
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<(GuildId, Self)> {
+        let guild_id = GuildId::new(row.get::<_, i64>("guild_id")? as u64);
+        let settings = Self {
+            prefix: row.get("prefix")?,
+            dj_role: row
+                .get::<_, Option<i64>>("dj_role")?
+                .map(|id| RoleId::new(id as u64)),
+            default_volume: row.get::<_, Option<f64>>("default_volume")?.map(|v| v as f32),
+            announce_channel: row
+                .get::<_, Option<i64>>("announce_channel")?
+                .map(|id| ChannelId::new(id as u64)),
+            idle_timeout: row.get("idle_timeout")?,
+            max_queue_length: row
+                .get::<_, Option<i64>>("max_queue_length")?
+                .map(|n| n as usize),
+            max_playlist_length: row
+                .get::<_, Option<i64>>("max_playlist_length")?
+                .map(|n| n as usize),
+            max_track_duration: row.get("max_track_duration")?,
+            autoplay: row.get("autoplay")?,
+            search_provider: row
+                .get::<_, Option<String>>("search_provider")?
+                .and_then(|prefix| SearchProvider::from_prefix(&prefix)),
+            normalize: row.get("normalize")?,
+            crossfade: row.get("crossfade")?,
+        };
+
+        Ok((guild_id, settings))
+    }
+}
+
+// Settings are read on every message for the prefix, so all rows are kept in
+// memory and the database is only touched on changes.
+#[derive(Clone)]
+pub struct GuildSettingsStore {
+    conn: Arc<Mutex<Connection>>,
+    cache: Arc<RwLock<HashMap<GuildId, GuildSettings>>>,
+}
+
+impl GuildSettingsStore {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        add_missing_columns(&conn)?;
+
+        let guilds = conn
+            .prepare("SELECT * FROM guild_settings")?
+            .query_map([], GuildSettings::from_row)?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        info!("GuildSettingsStore: Loaded settings for {} guilds", guilds.len());
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            cache: Arc::new(RwLock::new(guilds)),
+        })
+    }
+
+    pub async fn get(&self, guild_id: GuildId) -> GuildSettings {
+        self.cache
+            .read()
+            .await
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub async fn update<F>(&self, guild_id: GuildId, change: F) -> rusqlite::Result<GuildSettings>
+    where
+        F: FnOnce(&mut GuildSettings),
+    {
+        // Holding the cache lock keeps concurrent updates from overwriting
+        // each other's rows.
+        let mut cache = self.cache.write().await;
+        let mut settings = cache.get(&guild_id).cloned().unwrap_or_default();
+        change(&mut settings);
+
+        let conn = self.conn.clone();
+        let row = settings.clone();
+        tokio::task::spawn_blocking(move || {
+            write_row(&conn.lock().expect("Settings connection poisoned"), guild_id, &row)
+        })
+        .await
+        .expect("Settings write task panicked")?;
+
+        cache.insert(guild_id, settings.clone());
+
+        Ok(settings)
+    }
+}
+
+fn add_missing_columns(conn: &Connection) -> rusqlite::Result<()> {
+    let columns = conn
+        .prepare("SELECT name FROM pragma_table_info('guild_settings')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    for (name, kind) in ADDED_COLUMNS {
+        if !columns.iter().any(|column| column == name) {
+            info!("GuildSettingsStore: Adding column {}", name);
+            conn.execute_batch(&format!("ALTER TABLE guild_settings ADD COLUMN {} {}", name, kind))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_row(conn: &Connection, guild_id: GuildId, settings: &GuildSettings) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO guild_settings (
+            guild_id, prefix, dj_role, default_volume, announce_channel, idle_timeout,
+            max_queue_length, max_playlist_length, max_track_duration, autoplay, search_provider,
+            normalize, crossfade
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            guild_id.get() as i64,
+            settings.prefix,
+            settings.dj_role.map(|id| id.get() as i64),
+            settings.default_volume.map(f64::from),
+            settings.announce_channel.map(|id| id.get() as i64),
+            settings.idle_timeout,
+            settings.max_queue_length.map(|n| n as i64),
+            settings.max_playlist_length.map(|n| n as i64),
+            settings.max_track_duration,
+            settings.autoplay,
+            settings.search_provider.map(SearchProvider::prefix),
+            settings.normalize,
+            settings.crossfade,
+        ],
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::gateway::ShardManager;
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::Timestamp;
+use serenity::prelude::*;
+use songbird::Songbird;
+use tracing::{info, warn};
+
+use crate::settings::GuildSettingsStore;
+use crate::snapshot::SnapshotStore;
+
+// Remembers the text channel each guild last used a command in, so a
+// shutdown notice ends up where members are looking.
+#[derive(Clone, Default)]
+pub struct CommandChannels {
+    inner: Arc<RwLock<HashMap<GuildId, ChannelId>>>,
+}
+
+impl CommandChannels {
+    pub async fn record(&self, guild_id: GuildId, channel_id: ChannelId) {
+        self.inner.write().await.insert(guild_id, channel_id);
+    }
+
+    pub async fn get(&self, guild_id: GuildId) -> Option<ChannelId> {
+        self.inner.read().await.get(&guild_id).copied()
+    }
+}
+
+#[cfg(unix)]
+pub async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to register SIGTERM handler");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = sigterm.recv() => "SIGTERM",
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn wait_for_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl-C"
+}
+
+pub struct Shutdown<'a> {
+    pub http: &'a Http,
+    pub songbird: &'a Songbird,
+    pub snapshots: &'a SnapshotStore,
+    pub shard_manager: &'a ShardManager,
+    pub channels: &'a CommandChannels,
+    pub settings: &'a GuildSettingsStore,
+    pub notice: Option<&'a str>,
+}
+
+impl Shutdown<'_> {
+    pub async fn run(self) {
+        // Snapshot first: leaving the calls below drops their queues. This
+        // also stops the periodic saves, which would overwrite it.
+        self.snapshots.save_for_shutdown(self.songbird).await;
+
+        let guild_ids: Vec<_> = self
+            .songbird
+            .iter()
+            .map(|(guild_id, _)| GuildId::new(guild_id.0.get()))
+            .collect();
+        info!("Shutdown: Leaving {} voice channels", guild_ids.len());
+
+        for guild_id in guild_ids {
+            if let Some(notice) = self.notice {
+                self.post_notice(guild_id, notice).await;
+            }
+
+            if let Err(e) = self.songbird.remove(guild_id).await {
+                warn!("Shutdown: Failed to leave voice in guild {:?}: {}", guild_id, e);
+            }
+        }
+
+        self.shard_manager.shutdown_all().await;
+        info!("Shutdown: All shards stopped");
+    }
+
+    async fn post_notice(&self, guild_id: GuildId, notice: &str) {
+        let announce_channel = self.settings.get(guild_id).await.announce_channel;
+        let Some(channel_id) = announce_channel.or(self.channels.get(guild_id).await) else {
+            return;
+        };
+
+        let embed = CreateEmbed::default()
+            .color(0xffffff)
+            .title(notice)
+            .timestamp(Timestamp::now());
+        let builder = CreateMessage::default().add_embed(embed);
+
+        if let Err(e) = channel_id.send_message(self.http, builder).await {
+            warn!("Shutdown: Failed to post notice in guild {:?}: {}", guild_id, e);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::prelude::*;
+use songbird::tracks::{LoopState, PlayMode};
+use songbird::Songbird;
+use tracing::{debug, info, warn};
+
+use crate::commands::music::metadata::TrackMetadata;
+use crate::commands::music::play::{enqueue_with_metadata, join_voice_channel};
+use crate::commands::utils::{get_config, get_filters, get_guild_settings, get_track_cache, get_ytdlp};
+
+// Saves every guild's queue to disk so a restart (e.g. a redeploy) can pick
+// up where playback stopped.
+
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SavedLoop {
+    Off,
+    Times(usize),
+    Infinite,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GuildSnapshot {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    // The first entry is the track that was playing
+    pub tracks: Vec<TrackMetadata>,
+    pub position: Duration,
+    pub volume: f32,
+    pub looping: SavedLoop,
+    pub paused: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub guilds: Vec<GuildSnapshot>,
+}
+
+pub struct SnapshotStore {
+    path: PathBuf,
+    // Saving is held back until the previous snapshot has been restored, so an
+    // early save can't overwrite it with an empty one.
+    restored: AtomicBool,
+    restoring: AtomicBool,
+    // Set once shutdown takes its snapshot, after which the periodic save
+    // would only see the calls being left
+    shutting_down: AtomicBool,
+    // One save at a time, so a periodic one still running can't land after
+    // the shutdown one
+    saving: Mutex<()>,
+}
+
+impl SnapshotStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            restored: AtomicBool::new(false),
+            restoring: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            saving: Mutex::new(()),
+        }
+    }
+
+    pub async fn capture(manager: &Songbird) -> Snapshot {
+        let mut guilds = Vec::new();
+
+        for (guild_id, handler_lock) in manager.iter() {
+            let (channel_id, handles) = {
+                let handler = handler_lock.lock().await;
+                (handler.current_channel(), handler.queue().current_queue())
+            };
+
+            let (Some(channel_id), Some(current)) = (channel_id, handles.first()) else {
+                continue;
+            };
+
+            let (position, volume, looping, paused) = match current.get_info().await {
+                Ok(state) => (
+                    state.position,
+                    state.volume,
+                    match state.loops {
+                        LoopState::Infinite => SavedLoop::Infinite,
+                        LoopState::Finite(0) => SavedLoop::Off,
+                        LoopState::Finite(n) => SavedLoop::Times(n),
+                    },
+                    matches!(state.playing, PlayMode::Pause),
+                ),
+                Err(_) => (Duration::ZERO, 1.0, SavedLoop::Off, false),
+            };
+
+            guilds.push(GuildSnapshot {
+                guild_id: GuildId::new(guild_id.0.get()),
+                channel_id: ChannelId::new(channel_id.0.get()),
+                tracks: handles
+                    .iter()
+                    .map(|handle| TrackMetadata::clone(&handle.data::<TrackMetadata>()))
+                    .collect(),
+                position,
+                volume,
+                looping,
+                paused,
+            });
+        }
+
+        Snapshot { guilds }
+    }
+
+    pub async fn save(&self, manager: &Songbird) {
+        let _saving = self.saving.lock().await;
+        if self.shutting_down.load(Ordering::Acquire) {
+            debug!("SnapshotStore: Shutting down, skipping save");
+            return;
+        }
+
+        self.write_current(manager).await;
+    }
+
+    // The last save before the voice calls are left. Saves after it are
+    // skipped, so the queues it captured are what a restart restores.
+    pub async fn save_for_shutdown(&self, manager: &Songbird) {
+        let _saving = self.saving.lock().await;
+        if self.shutting_down.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        self.write_current(manager).await;
+    }
+
+    async fn write_current(&self, manager: &Songbird) {
+        if !self.restored.load(Ordering::Acquire) {
+            debug!("SnapshotStore: Previous snapshot not restored yet, skipping save");
+            return;
+        }
+
+        let snapshot = Self::capture(manager).await;
+
+        if let Err(e) = self.write(&snapshot).await {
+            warn!("SnapshotStore: Failed to write {}: {}", self.path.display(), e);
+        } else {
+            debug!("SnapshotStore: Saved {} guild queues", snapshot.guilds.len());
+        }
+    }
+
+    async fn write(&self, snapshot: &Snapshot) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let json = serde_json::to_vec_pretty(snapshot)?;
+
+        // Write to a temporary file first so a crash mid-write keeps the old one
+        let tmp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, json).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await
+    }
+
+    async fn read(&self) -> Option<Snapshot> {
+        let bytes = match tokio::fs::read(&self.path).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                debug!("SnapshotStore: No snapshot at {}: {}", self.path.display(), e);
+                return None;
+            }
+        };
+
+        match serde_json::from_slice(&bytes) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                warn!("SnapshotStore: Ignoring unreadable snapshot {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    // Rejoins every saved voice channel and rebuilds its queue. Only the
+    // first call does anything, as `ready` fires again on reconnects.
+    pub async fn restore(&self, ctx: &Context) {
+        if self.restoring.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        if let Some(snapshot) = self.read().await {
+            info!("SnapshotStore: Restoring {} guild queues", snapshot.guilds.len());
+
+            for guild in snapshot.guilds {
+                let guild_id = guild.guild_id;
+                if let Err(e) = restore_guild(ctx, guild).await {
+                    warn!("SnapshotStore: Failed to restore guild {:?}: {}", guild_id, e);
+                }
+            }
+        }
+
+        self.restored.store(true, Ordering::Release);
+    }
+
+    pub fn spawn_periodic(self: Arc<Self>, manager: Arc<Songbird>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+            while !self.shutting_down.load(Ordering::Acquire) {
+                interval.tick().await;
+                self.save(&manager).await;
+            }
+        });
+    }
+}
+
+async fn restore_guild(ctx: &Context, guild: GuildSnapshot) -> Result<(), String> {
+    if guild.tracks.is_empty() {
+        return Ok(());
+    }
+
+    join_voice_channel(ctx, guild.guild_id, guild.channel_id).await?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = manager
+        .get(guild.guild_id)
+        .ok_or_else(|| "Voice connection vanished after joining".to_string())?;
+
+    let ytdlp = get_ytdlp(ctx).await;
+    let config = get_config(ctx).await;
+    let settings = get_guild_settings(ctx, guild.guild_id).await;
+    let cache = get_track_cache(ctx).await;
+    let filters = get_filters(ctx, guild.guild_id).await;
+    let mut handler = handler_lock.lock().await;
+    let mut handles = Vec::with_capacity(guild.tracks.len());
+    for metadata in guild.tracks {
+        let source = cache.input(&metadata, &ytdlp);
+        handles.push(enqueue_with_metadata(
+            &mut handler,
+            source,
+            metadata,
+            settings.default_volume(config),
+            &filters,
+        ));
+    }
+
+    let current = &handles[0];
+    let _ = current.set_volume(guild.volume);
+    let _ = match guild.looping {
+        SavedLoop::Off => Ok(()),
+        SavedLoop::Times(n) => current.loop_for(n),
+        SavedLoop::Infinite => current.enable_loop(),
+    };
+    if !guild.position.is_zero() {
+        drop(current.seek(guild.position));
+    }
+    if guild.paused {
+        let _ = current.pause();
+    }
+
+    info!(
+        "SnapshotStore: Restored {} tracks in guild {:?}",
+        handles.len(),
+        guild.guild_id
+    );
+
+    Ok(())
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{self, Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::{env, fmt, fs, io};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use serenity::async_trait;
+use songbird::input::core::io::MediaSource;
+use songbird::input::{AudioStream, AudioStreamError, Compose, HlsRequest, HttpRequest, Input};
+use tokio::process::{Child, Command as TokioCommand};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{timeout, Duration, Instant};
+use tracing::debug;
+
+use crate::config::Config;
+
+// Every yt-dlp run made for a command goes through here: at most
+// `ytdlp_workers` processes at once, each cut off after `ytdlp_timeout`.
+// Children are killed when dropped, so a lookup that times out or whose
+// command is cancelled takes its process down with it. Cache downloads have
+// their own, slower lane, and so do playlist imports, which run for as long
+// as the playlist takes to list. Playback included: tracks are streamed from
+// the URL yt-dlp finds, looked up here rather than by songbird.
+
+// How long a lookup waits for a free worker before it is turned down
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Playlists listed at once, on top of the workers
+const PLAYLIST_STREAMS: usize = 2;
+
+// The format songbird's own yt-dlp source streams
+const AUDIO_FORMAT: &str = "ba[abr>0][vcodec=none]/best";
+
+// How long a stream URL found while resolving a track is trusted. Sites let
+// them expire, so a track played later is looked up again.
+const STREAM_URL_LIFETIME: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug)]
+pub enum YtDlpError {
+    // Every worker, or playlist slot, stayed busy for the whole `QUEUE_TIMEOUT`
+    Busy,
+    TimedOut,
+    Spawn(io::Error),
+    // yt-dlp exited with an error, or printed nothing usable
+    Failed(String),
+}
+
+impl fmt::Display for YtDlpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YtDlpError::Busy => write!(f, "Too many tracks are being looked up, try again in a moment."),
+            YtDlpError::TimedOut => write!(f, "Looking up the track took too long."),
+            YtDlpError::Spawn(_) | YtDlpError::Failed(_) => write!(f, "Could not load the track."),
+        }
+    }
+}
+
+impl std::error::Error for YtDlpError {}
+
+// The fields of `yt-dlp -j` we keep
+#[derive(Deserialize)]
+pub struct TrackInfo {
+    pub title: Option<String>,
+    pub track: Option<String>,
+    pub webpage_url: Option<String>,
+    pub duration: Option<f64>,
+    // Where the audio itself is, with what it takes to fetch it
+    pub url: Option<String>,
+    pub http_headers: Option<HashMap<String, String>>,
+    pub protocol: Option<String>,
+    pub filesize: Option<u64>,
+}
+
+impl TrackInfo {
+    pub fn stream(&self) -> Option<StreamUrl> {
+        let url = self.url.clone()?;
+        let headers = self
+            .http_headers
+            .iter()
+            .flatten()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::from_bytes(name.as_bytes()).ok()?,
+                    HeaderValue::from_str(value).ok()?,
+                ))
+            })
+            .collect();
+
+        Some(StreamUrl {
+            url,
+            headers,
+            hls: self.protocol.as_deref() == Some("m3u8_native"),
+            filesize: self.filesize,
+            found: Instant::now(),
+        })
+    }
+}
+
+// The audio of a track, as yt-dlp found it
+#[derive(Clone)]
+pub struct StreamUrl {
+    url: String,
+    headers: HeaderMap,
+    hls: bool,
+    filesize: Option<u64>,
+    found: Instant,
+}
+
+// One line of `yt-dlp -j --flat-playlist`. The entries aren't resolved, but
+// carry enough to queue or list them.
+#[derive(Deserialize)]
+pub struct FlatEntry {
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub duration: Option<f64>,
+}
+
+// A running yt-dlp whose output is read as it comes. Holds its slot until
+// dropped, which also kills the process.
+pub struct YtDlpProcess {
+    pub child: Child,
+    _permit: OwnedSemaphorePermit,
+    _cookies: Option<CookieCopy>,
+}
+
+// yt-dlp writes its cookie jar back to the file when it exits, so runs side
+// by side would overwrite each other's changes and could leave it mangled.
+// Each run gets its own copy of the configured file instead, removed once
+// the copy is dropped; the original is only ever read.
+pub struct CookieCopy {
+    path: PathBuf,
+}
+
+impl CookieCopy {
+    pub async fn new(original: &Path) -> io::Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "rmusicbot-cookies-{}-{}.txt",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::copy(original, &path).await?;
+
+        Ok(Self { path })
+    }
+
+    // `None` when no cookies file is configured
+    pub async fn for_run(original: Option<&Path>) -> io::Result<Option<Self>> {
+        match original {
+            Some(original) => Self::new(original).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn args(&self) -> [&OsStr; 2] {
+        ["--cookies".as_ref(), self.path.as_os_str()]
+    }
+}
+
+impl Drop for CookieCopy {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Clone)]
+pub struct YtDlp {
+    path: &'static str,
+    args: Vec<String>,
+    cookies: Option<&'static Path>,
+    http_client: HttpClient,
+    workers: Arc<Semaphore>,
+    streams: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl YtDlp {
+    pub fn new(config: &'static Config, http_client: HttpClient) -> Self {
+        Self {
+            path: &config.ytdlp_path,
+            args: config.ytdlp_args(),
+            cookies: config.ytdlp_cookies.as_deref(),
+            http_client,
+            workers: Arc::new(Semaphore::new(config.ytdlp_workers)),
+            streams: Arc::new(Semaphore::new(PLAYLIST_STREAMS)),
+            timeout: Duration::from_secs(config.ytdlp_timeout),
+        }
+    }
+
+    // The limit for one run, or for a single line of a streamed one
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn http_client(&self) -> &HttpClient {
+        &self.http_client
+    }
+
+    // Playback of a track, looked up once songbird gets to it
+    pub fn source(&self, url: &str) -> YtDlpSource {
+        YtDlpSource {
+            ytdlp: self.clone(),
+            url: url.to_string(),
+            stream: None,
+        }
+    }
+
+    // Playback of a track just resolved, which needs no second lookup if it
+    // is played soon
+    pub fn resolved_source(&self, url: &str, info: &TrackInfo) -> YtDlpSource {
+        YtDlpSource {
+            stream: info.stream(),
+            ..self.source(url)
+        }
+    }
+
+    async fn worker(&self) -> Result<OwnedSemaphorePermit, YtDlpError> {
+        Self::acquire(&self.workers).await
+    }
+
+    async fn acquire(slots: &Arc<Semaphore>) -> Result<OwnedSemaphorePermit, YtDlpError> {
+        match timeout(QUEUE_TIMEOUT, slots.clone().acquire_owned()).await {
+            Ok(permit) => Ok(permit.expect("yt-dlp semaphore closed")),
+            Err(_) => {
+                debug!("YtDlp: Nothing free after {:?}", QUEUE_TIMEOUT);
+                Err(YtDlpError::Busy)
+            }
+        }
+    }
+
+    fn command(&self, args: &[&str], cookies: Option<&CookieCopy>) -> TokioCommand {
+        let mut command = TokioCommand::new(self.path);
+        command
+            .args(&self.args)
+            .args(cookies.into_iter().flat_map(CookieCopy::args))
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+        command
+    }
+
+    // Runs yt-dlp to completion and returns what it printed
+    pub async fn output(&self, args: &[&str]) -> Result<Output, YtDlpError> {
+        let _permit = self.worker().await?;
+        let cookies = CookieCopy::for_run(self.cookies).await.map_err(YtDlpError::Spawn)?;
+        let child = self
+            .command(args, cookies.as_ref())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(YtDlpError::Spawn)?;
+
+        // On timeout the child is dropped with the future, and killed
+        let output = timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| YtDlpError::TimedOut)?
+            .map_err(YtDlpError::Spawn)?;
+        if !output.status.success() {
+            return Err(YtDlpError::Failed(format!(
+                "yt-dlp exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(output)
+    }
+
+    // Starts yt-dlp with its stdout piped, for output that arrives over time.
+    // It may be kept running for minutes, so it takes a stream slot rather
+    // than one of the workers lookups wait for.
+    pub async fn spawn(&self, args: &[&str]) -> Result<YtDlpProcess, YtDlpError> {
+        let permit = Self::acquire(&self.streams).await?;
+        let cookies = CookieCopy::for_run(self.cookies).await.map_err(YtDlpError::Spawn)?;
+        let child = self
+            .command(args, cookies.as_ref())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(YtDlpError::Spawn)?;
+
+        Ok(YtDlpProcess {
+            child,
+            _permit: permit,
+            _cookies: cookies,
+        })
+    }
+
+    // Looks up a single track, `query` being a URL or e.g. `ytsearch1:<terms>`.
+    // Of a search page only the first result is resolved.
+    pub async fn resolve(&self, query: &str) -> Result<TrackInfo, YtDlpError> {
+        let output = self
+            .output(&["-j", "-f", AUDIO_FORMAT, "--no-playlist", "--playlist-items", "1", query])
+            .await?;
+
+        let line = output
+            .stdout
+            .split(|&byte| byte == b'\n')
+            .find(|line| !line.is_empty())
+            .ok_or_else(|| YtDlpError::Failed(format!("no results for '{}'", query)))?;
+
+        serde_json::from_slice(line).map_err(|e| YtDlpError::Failed(e.to_string()))
+    }
+
+    // The first `limit` entries of a playlist or search, without resolving them
+    pub async fn list(&self, query: &str, limit: usize) -> Result<Vec<FlatEntry>, YtDlpError> {
+        let limit = limit.to_string();
+        let output = self
+            .output(&["-j", "--flat-playlist", "--playlist-end", &limit, query])
+            .await?;
+
+        Ok(output
+            .stdout
+            .split(|&byte| byte == b'\n')
+            .filter_map(|line| serde_json::from_slice(line).ok())
+            .collect())
+    }
+}
+
+// A track streamed from wherever yt-dlp says its audio is
+pub struct YtDlpSource {
+    ytdlp: YtDlp,
+    url: String,
+    stream: Option<StreamUrl>,
+}
+
+impl YtDlpSource {
+    async fn open(
+        &self,
+        stream: StreamUrl,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let client = self.ytdlp.http_client.clone();
+        if stream.hls {
+            HlsRequest::new_with_headers(client, stream.url, stream.headers).create()
+        } else {
+            HttpRequest {
+                client,
+                request: stream.url,
+                headers: stream.headers,
+                content_length: stream.filesize,
+            }
+            .create_async()
+            .await
+        }
+    }
+}
+
+impl From<YtDlpSource> for Input {
+    fn from(source: YtDlpSource) -> Self {
+        Input::Lazy(Box::new(source))
+    }
+}
+
+#[async_trait]
+impl Compose for YtDlpSource {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+
+    async fn create_async(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        // A URL that no longer works is looked up again below
+        if let Some(stream) = self
+            .stream
+            .take()
+            .filter(|stream| stream.found.elapsed() < STREAM_URL_LIFETIME)
+        {
+            match self.open(stream).await {
+                Ok(audio) => return Ok(audio),
+                Err(e) => debug!("YtDlpSource: Stream of {} failed, looking it up again: {}", self.url, e),
+            }
+        }
+
+        let info = self
+            .ytdlp
+            .resolve(&self.url)
+            .await
+            .map_err(|e| AudioStreamError::Fail(Box::new(e)))?;
+        let stream = info.stream().ok_or_else(|| {
+            AudioStreamError::Fail(format!("yt-dlp found no stream for '{}'", self.url).into())
+        })?;
+
+        self.open(stream).await
+    }
+
+    fn should_create_async(&self) -> bool {
+        true
+    }
+}